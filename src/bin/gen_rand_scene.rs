@@ -1,6 +1,11 @@
 use pathtracer::{
     renderer::{
-        camera::CameraSettings, materials::*, scene::Scene, sphere::Sphere, vector3::Vector3,
+        bvh::{BVHBuilder, BVHTree},
+        camera::CameraSettings,
+        materials::*,
+        scene::Scene,
+        sphere::Sphere,
+        vector3::Vector3,
     },
     save_ron,
 };
@@ -20,6 +25,8 @@ fn gen_scene() -> Scene {
         yaw,
         pitch,
         vfov: 20.0,
+        aperture: 0.1,
+        focus_distance: 10.0,
     };
 
     let mut spheres = vec![];
@@ -28,6 +35,7 @@ fn gen_scene() -> Scene {
     let mut lambertians = vec![];
     let mut metals = vec![];
     let mut glass = vec![];
+    let mut principled = vec![];
 
     // ground
     spheres.push(Sphere::new(
@@ -76,7 +84,7 @@ fn gen_scene() -> Scene {
                 b as f32 + 0.9 * rng.gen::<f32>(),
             );
             if pos.length() < size as f32 {
-                if mat < 0.25 {
+                if mat < 0.2 {
                     let albedo = Vector3::new(
                         rng.gen::<f32>() * rng.gen::<f32>(),
                         rng.gen::<f32>() * rng.gen::<f32>(),
@@ -89,7 +97,7 @@ fn gen_scene() -> Scene {
                         lambertians.len() as u32,
                     ));
                     lambertians.push(Lambertian::new(albedo));
-                } else if mat < 0.5 {
+                } else if mat < 0.35 {
                     let albedo = Vector3::new(
                         rng.gen_range(0.5..1.0),
                         rng.gen_range(0.5..1.0),
@@ -98,9 +106,24 @@ fn gen_scene() -> Scene {
                     let roughness = rng.gen_range(0.0..0.5);
                     spheres.push(Sphere::new(pos, 0.2, indecies::METAL, metals.len() as u32));
                     metals.push(Metal::new(albedo, roughness));
-                } else if mat < 0.75 {
+                } else if mat < 0.5 {
                     spheres.push(Sphere::new(pos, 0.2, indecies::GLASS, glass.len() as u32));
                     glass.push(Glass::new(rng.gen_range(1.0..4.0)));
+                } else if mat < 0.75 {
+                    let base_colour = Vector3::new(rng.gen(), rng.gen(), rng.gen());
+                    let metallic = if rng.gen_bool(0.3) { 1.0 } else { 0.0 };
+                    spheres.push(Sphere::new(
+                        pos,
+                        0.2,
+                        indecies::PRINCIPLED,
+                        principled.len() as u32,
+                    ));
+                    principled.push(Principled {
+                        transmission: if rng.gen_bool(0.2) { 1.0 } else { 0.0 },
+                        clearcoat: rng.gen_range(0.0..1.0),
+                        sheen: rng.gen_range(0.0..1.0),
+                        ..Principled::new(base_colour, metallic, rng.gen_range(0.0..1.0))
+                    });
                 } else {
                     spheres.push(Sphere::new(pos, 0.2, indecies::LIGHT, 0));
                 }
@@ -111,14 +134,28 @@ fn gen_scene() -> Scene {
     Scene {
         camera,
         spheres,
+        vertices: vec![],
+        triangles: vec![],
+        environment: Default::default(),
         lights,
         lambertians,
         metals,
         glass,
+        conductors: vec![],
+        rough_glass: vec![],
+        principled,
+        textures: vec![],
     }
 }
 
 fn main() {
     let scene = gen_scene();
+
+    // compare how expensive the scene is to trace with each bvh builder
+    for builder in [BVHBuilder::Median, BVHBuilder::Sah] {
+        let bvh = BVHTree::new(builder, &scene.spheres, &scene.vertices, &scene.triangles);
+        println!("{builder:?} BVH cost: {:.2}", bvh.cost());
+    }
+
     save_ron("scene.ron", &scene);
 }
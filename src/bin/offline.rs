@@ -1,86 +1,454 @@
+use clap::Parser;
 use pathtracer::{
-    load_ron,
-    renderer::{scene::Scene, *},
+    checkpoint::{self, Checkpoint, CheckpointHeader},
+    convergence::{relative_variance, StopReason},
+    load_ron, load_scene,
+    renderer::{aov::AovImages, denoiser::Denoiser, scene::Scene, *},
+    save_aovs, save_image,
 };
-use std::io::{stderr, Write};
+use std::{
+    io::{stderr, Write},
+    path::PathBuf,
+    process::ExitCode,
+    time::{Duration, Instant},
+};
+
+/// Renders a scene to an image without opening a window
+#[derive(Parser, Debug)]
+#[command(version)]
+struct Args {
+    /// .ron, .gltf or .glb scene
+    #[arg(default_value = "scene1.ron")]
+    scene: PathBuf,
+    #[arg(long, default_value = "settings.ron")]
+    settings: PathBuf,
+    #[arg(long, default_value_t = 1280)]
+    width: u32,
+    #[arg(long, default_value_t = 720)]
+    height: u32,
+    /// frames to accumulate, each traces the settings' `samples` rays per pixel.
+    /// With a time budget or noise threshold this is the most frames rendered
+    #[arg(short, long, default_value_t = 1000, value_parser = clap::value_parser!(u32).range(1..))]
+    samples: u32,
+    /// max depth, overrides the settings
+    #[arg(short, long)]
+    depth: Option<i32>,
+    /// seed for a reproducible render, random when not given
+    #[arg(long)]
+    seed: Option<u64>,
+    /// the format is picked from the extension, .exr and .hdr keep the linear floats
+    #[arg(short, long, default_value = "img.png")]
+    output: PathBuf,
+    /// renders square tiles of this size one at a time and stitches them together,
+    /// only frames bigger than the gpu's texture limit are tiled by default
+    #[arg(long)]
+    tile_size: Option<u32>,
+    /// writes a checkpoint every this many frames, 0 turns them off
+    #[arg(long, default_value_t = 100)]
+    checkpoint_every: u32,
+    /// where checkpoints are written, defaults to the output with .checkpoint added
+    #[arg(long)]
+    checkpoint: Option<PathBuf>,
+    /// continues from a checkpoint, the scene, resolution and tile size have to match
+    #[arg(long)]
+    resume: Option<PathBuf>,
+    /// stops after this many seconds, tiles share what's left of the budget evenly
+    #[arg(long)]
+    time_budget: Option<f32>,
+    /// stops once the estimated relative variance of the pixels falls below this, like 0.001
+    #[arg(long)]
+    noise_threshold: Option<f32>,
+    /// also saves the albedo, normal, position, depth and ids of the first hits next to the
+    /// output, like img.albedo.png. Also turned on by `aovs: true` in the settings
+    #[arg(long)]
+    aovs: bool,
+    /// runs the denoiser over every finished tile, the denoiser settings are in the settings.
+    /// Tiles are rendered with a band of the denoiser's reach around them so there are no seams
+    #[arg(long)]
+    denoise: bool,
+}
+
+/// tile size used when the frame doesn't fit in one texture
+const DEFAULT_TILE_SIZE: u32 = 2048;
+/// frames between noise estimates, reading the textures back isn't free
+const NOISE_CHECK_EVERY: u32 = 16;
 
-fn main() {
+fn main() -> ExitCode {
     #[cfg(debug_assertions)]
     simple_logger::init_with_level(log::Level::Warn).unwrap();
 
-    let time = std::time::Instant::now();
-    let ctx = pollster::block_on(WgpuContext::new());
+    let args = Args::parse();
 
-    // HD
-    let width = 1280;
-    let height = 720;
+    let time = Instant::now();
+    let Some(ctx) = pollster::block_on(WgpuContext::new()) else {
+        eprintln!("no suitable gpu found");
+        return ExitCode::FAILURE;
+    };
 
-    // 2k
-    // let width = 2048;
-    // let height = 1080;
+    let Some(scene): Option<Scene> = load_scene(&args.scene) else {
+        eprintln!("{}: couldn't load scene", args.scene.display());
+        return ExitCode::FAILURE;
+    };
+    let Some(mut settings): Option<Settings> = load_ron(&args.settings) else {
+        eprintln!("{}: couldn't load settings", args.settings.display());
+        return ExitCode::FAILURE;
+    };
+    if let Some(depth) = args.depth {
+        settings.depth = depth;
+    }
+    // the camera never moves
+    settings.temporal = false;
 
-    // 4k
-    // let width = 3840;
-    // let height = 2160;
+    if args.width == 0 || args.height == 0 {
+        eprintln!("resolution has to be at least 1");
+        return ExitCode::FAILURE;
+    }
+    let (width, height) = (args.width, args.height);
+    let limit = ctx.device.limits().max_texture_dimension_2d;
+    // denoised tiles need the pixels around them, so they don't show seams
+    let guard = if args.denoise {
+        settings.denoiser.reach()
+    } else {
+        0
+    };
+    let tile_size = args.tile_size.unwrap_or(if width.max(height) > limit {
+        DEFAULT_TILE_SIZE.min(limit.saturating_sub(guard.saturating_mul(2)).max(1))
+    } else {
+        width.max(height)
+    });
+    if tile_size == 0 || tile_size > limit {
+        eprintln!("tile size has to be between 1 and {limit}");
+        return ExitCode::FAILURE;
+    }
+    let budget = match args.time_budget {
+        Some(t) if !t.is_finite() || t <= 0.0 => {
+            eprintln!("time budget has to be a positive number of seconds");
+            return ExitCode::FAILURE;
+        }
+        t => t.map(Duration::from_secs_f32),
+    };
 
-    // 8k
-    // let width = 7680;
-    // let height = 4320;
+    let scene_hash = checkpoint::scene_hash(&scene);
+    let checkpoint_path = args.checkpoint.clone().unwrap_or_else(|| {
+        let mut path = args.output.clone().into_os_string();
+        path.push(".checkpoint");
+        path.into()
+    });
 
-    // 16k too big for gpu
-    // let width = 15360;
-    // let height = 8640;
+    // pick up where an interrupted render left off
+    let mut resume = match &args.resume {
+        Some(path) => match Checkpoint::load(path) {
+            Ok(checkpoint) => Some(checkpoint),
+            Err(e) => {
+                eprintln!("{}: {e}", path.display());
+                return ExitCode::FAILURE;
+            }
+        },
+        None => None,
+    };
+    if let Some(checkpoint) = &resume {
+        let header = &checkpoint.header;
+        if header.scene_hash != scene_hash {
+            eprintln!("the checkpoint is of a different scene");
+            return ExitCode::FAILURE;
+        }
+        if [header.width, header.height, header.tile_size] != [width, height, tile_size] {
+            eprintln!(
+                "the checkpoint is {}x{} with a tile size of {}",
+                header.width, header.height, header.tile_size
+            );
+            return ExitCode::FAILURE;
+        }
+        if header.guard != guard {
+            eprintln!(
+                "the checkpoint's tiles have a guard band of {} pixels, not {guard}",
+                header.guard
+            );
+            return ExitCode::FAILURE;
+        }
+        settings = header.settings;
+    }
+    // a resumed render keeps writing aovs if it did before, the settings might only have
+    // had them on for the denoiser
+    let save_aovs_too = args.aovs
+        || match &resume {
+            Some(checkpoint) => checkpoint.header.aovs,
+            None => settings.aovs,
+        };
+    if save_aovs_too
+        && resume
+            .as_ref()
+            .is_some_and(|c| c.header.tile > 0 && !c.header.aovs)
+    {
+        eprintln!("the checkpoint doesn't have the aovs of the tiles finished before it");
+        return ExitCode::FAILURE;
+    }
+    // the denoiser needs the aovs
+    settings.aovs = save_aovs_too || args.denoise;
 
-    let samples = 1000;
+    let tiles = checkpoint::tiles(width, height, tile_size);
+    let guarded = |tile| checkpoint::guarded(tile, guard, width, height);
+    if tiles
+        .iter()
+        .any(|&tile| guarded(tile).1.iter().any(|&s| s > limit))
+    {
+        eprintln!("tiles with a guard band of {guard} pixels have to fit in {limit}");
+        return ExitCode::FAILURE;
+    }
+    let (first_tile, mut img) = match &resume {
+        Some(checkpoint) => (checkpoint.header.tile, checkpoint.frame.clone()),
+        None => (0, image::Rgba32FImage::new(width, height)),
+    };
+    let mut aovs = save_aovs_too.then(|| {
+        resume
+            .as_mut()
+            .and_then(|checkpoint| checkpoint.aov_frame.take())
+            .unwrap_or_else(|| AovImages::new(width, height))
+    });
+    // checkpoints written somewhere else need the finished tiles too
+    if args.checkpoint_every > 0 && args.resume.as_ref() != Some(&checkpoint_path) {
+        for (i, &(offset, [w, h])) in tiles.iter().enumerate().take(first_tile) {
+            let aov_tile = aovs.as_ref().map(|aovs| aovs.crop(offset, [w, h]));
+            let tile = image::imageops::crop_imm(&img, offset[0], offset[1], w, h).to_image();
+            if let Err(e) =
+                Checkpoint::save_tile(&checkpoint_path, &tiles, i, &tile, aov_tile.as_ref())
+            {
+                eprintln!("{}: {e}", checkpoint_path.display());
+            }
+        }
+    }
+    let mut denoiser = args
+        .denoise
+        .then(|| Denoiser::new(&ctx.device, settings.denoiser));
+
+    let Some((_, [tile_width, tile_height])) = tiles.get(first_tile).map(|&tile| guarded(tile))
+    else {
+        eprintln!("the checkpoint's tile is out of range");
+        return ExitCode::FAILURE;
+    };
+    let mut renderer = Renderer::new(
+        &ctx.device,
+        &ctx.queue,
+        scene,
+        settings,
+        tile_width,
+        tile_height,
+    );
+    // resumed renders continue the same frames
+    let seed = resume.as_ref().map(|c| c.header.seed).or(args.seed);
+    renderer.set_seed(seed.unwrap_or_else(|| renderer.seed()));
+
+    let samples = args.samples;
+    // why every tile stopped and how many frames it got
+    let mut stops = vec![];
+    for (i, &(offset, size)) in tiles.iter().enumerate().skip(first_tile) {
+        let (guarded_offset, guarded_size) = guarded((offset, size));
+        renderer.set_tile(&ctx.device, guarded_offset, guarded_size, [width, height]);
+        // where the tile is in the rendered one
+        let inner = [offset[0] - guarded_offset[0], offset[1] - guarded_offset[1]];
+        let tile_progress = if tiles.len() > 1 {
+            format!("tile {}/{} | ", i + 1, tiles.len())
+        } else {
+            String::new()
+        };
+
+        let mut start = 1;
+        if let Some(checkpoint) = resume.as_ref().filter(|_| i == first_tile) {
+            renderer.resume(
+                &ctx.device,
+                &ctx.queue,
+                &checkpoint.tile,
+                &checkpoint.moments,
+                checkpoint.aov_sums.as_ref().filter(|_| save_aovs_too),
+                checkpoint.header.samples,
+            );
+            start = checkpoint.header.samples as u32 + 1;
+        }
+
+        let deadline = budget.map(|budget| {
+            let left = budget.saturating_sub(time.elapsed());
+            Instant::now() + left / (tiles.len() - i) as u32
+        });
+        let mut stop = StopReason::Samples;
+        let mut noise = String::new();
+
+        for s in start..=samples {
+            let mut encoder = ctx
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("Encoder"),
+                });
+            renderer.render(&ctx.device, &mut encoder);
+            ctx.queue.submit([encoder.finish()]);
+            ctx.device.poll(wgpu::Maintain::Wait);
+
+            let mut lock = stderr().lock();
+            write!(
+                lock,
+                "\r{}{}/{} | {:.1}% {}",
+                tile_progress,
+                s,
+                samples,
+                s as f32 / samples as f32 * 100.0,
+                noise
+            )
+            .unwrap();
+            lock.flush().unwrap();
+            drop(lock);
 
-    // load scene
-    let scene: Scene = load_ron("scene1.ron").unwrap_or_default();
-    // load settings
-    let settings: Settings = load_ron("settings.ron").unwrap();
+            if args.checkpoint_every > 0 && s % args.checkpoint_every == 0 && s < samples {
+                let header = CheckpointHeader {
+                    scene_hash,
+                    settings,
+                    seed: renderer.seed(),
+                    width,
+                    height,
+                    tile_size,
+                    guard,
+                    tile: i,
+                    samples: renderer.samples(),
+                    aovs: save_aovs_too,
+                };
+                let (tile, moments) = read_textures(&ctx, &renderer);
+                let aov_sums = save_aovs_too
+                    .then(|| read_aov_sums(&ctx, &renderer))
+                    .flatten();
+                if let Err(e) = Checkpoint::save(
+                    &checkpoint_path,
+                    &header,
+                    &tile,
+                    &moments,
+                    aov_sums.as_ref(),
+                ) {
+                    eprintln!("{}: {e}", checkpoint_path.display());
+                }
+            }
 
-    let mut renderer = Renderer::new(&ctx.device, scene, settings, width, height);
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                stop = StopReason::TimeBudget;
+                break;
+            }
+            if let Some(threshold) = args.noise_threshold {
+                if s % NOISE_CHECK_EVERY == 0 {
+                    let (tile, moments) = read_textures(&ctx, &renderer);
+                    let variance = relative_variance(&tile, &moments);
+                    noise = format!("| noise {variance:.2e} ");
+                    if variance < threshold {
+                        stop = StopReason::NoiseThreshold;
+                        break;
+                    }
+                }
+            }
+        }
+        // moments alpha counts the samples every pixel got
+        let (_, moments) = read_textures(&ctx, &renderer);
+        let rays = moments.pixels().map(|m| m[3].round() as u64);
+        let least = rays.clone().min().unwrap_or(0);
+        let most = rays.max().unwrap_or(0);
+        stops.push((stop, least, most));
 
-    let mut lock = stderr().lock();
-    for s in 1..=samples {
+        // stitch the averaged tile into the frame
         let mut encoder = ctx
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor {
                 label: Some("Encoder"),
             });
-        renderer.render(&ctx.device, &mut encoder);
+        let save_info = match &mut denoiser {
+            Some(denoiser) => {
+                denoiser.denoise(&ctx.device, &mut encoder, &renderer);
+                denoiser.start_save(&ctx.device, &mut encoder)
+            }
+            None => renderer.start_save(&ctx.device, &mut encoder),
+        };
+        let aov_save_info = renderer.start_save_aovs(&ctx.device, &mut encoder);
         ctx.queue.submit([encoder.finish()]);
-        ctx.device.poll(wgpu::Maintain::Wait);
-
-        write!(
-            lock,
-            "\r{}/{} | {:.1}% ",
-            s,
-            samples,
-            s as f32 / samples as f32 * 100.0
-        )
-        .unwrap();
-        stderr().flush().unwrap();
+        let tile = save_info.finish(&ctx.device, settings.outlier_rejection);
+        let tile =
+            image::imageops::crop_imm(&tile, inner[0], inner[1], size[0], size[1]).to_image();
+        image::imageops::replace(&mut img, &tile, offset[0] as i64, offset[1] as i64);
+        let mut aov_tile = None;
+        if let (Some(aovs), Some(aov_save_info)) = (&mut aovs, aov_save_info) {
+            let tile = AovImages::from_sums(aov_save_info.map(|info| info.read(&ctx.device)))
+                .crop(inner, size);
+            aovs.replace(&tile, offset);
+            aov_tile = Some(tile);
+        }
+        if args.checkpoint_every > 0 && i + 1 < tiles.len() {
+            if let Err(e) =
+                Checkpoint::save_tile(&checkpoint_path, &tiles, i, &tile, aov_tile.as_ref())
+            {
+                eprintln!("{}: {e}", checkpoint_path.display());
+            }
+        }
     }
-    drop(lock);
     eprintln!();
+
     // save
+    println!("Img size: {}, {}", width, height);
+    if tiles.len() > 1 {
+        println!("Tiles: {}", tiles.len());
+    }
+    let least = stops.iter().map(|&(_, least, _)| least).min().unwrap_or(0);
+    let most = stops.iter().map(|&(_, _, most)| most).max().unwrap_or(0);
+    if least == most {
+        println!("Samples: {}", most);
+    } else {
+        println!("Samples: {} to {}", least, most);
+    }
+    for reason in [
+        StopReason::Samples,
+        StopReason::TimeBudget,
+        StopReason::NoiseThreshold,
+    ] {
+        let count = stops.iter().filter(|&&(stop, _, _)| stop == reason).count();
+        if count > 0 && stops.len() > 1 {
+            println!("Stopped by: {reason} ({count} tiles)");
+        } else if count > 0 {
+            println!("Stopped by: {reason}");
+        }
+    }
+    if let Err(e) = save_image(&args.output, &img, &settings.tone_mapping) {
+        eprintln!("{}: {e}", args.output.display());
+        return ExitCode::FAILURE;
+    }
+    if let Some(aovs) = &aovs {
+        if let Err(e) = save_aovs(&args.output, aovs) {
+            eprintln!("{}: couldn't save aovs: {e}", args.output.display());
+            return ExitCode::FAILURE;
+        }
+    }
+    // the render is done, so the checkpoint isn't needed anymore
+    Checkpoint::remove(&checkpoint_path);
+
+    println!("Time took: {}s", time.elapsed().as_secs());
+    ExitCode::SUCCESS
+}
+
+/// raw sums of the renderer's colours and squared colours
+fn read_textures(
+    ctx: &WgpuContext,
+    renderer: &Renderer,
+) -> (image::Rgba32FImage, image::Rgba32FImage) {
     let mut encoder = ctx
         .device
         .create_command_encoder(&wgpu::CommandEncoderDescriptor {
             label: Some("Encoder"),
         });
-    let save_info = renderer.start_save(&ctx.device, &mut encoder);
+    let sum = renderer.start_save(&ctx.device, &mut encoder);
+    let moments = renderer.start_save_moments(&ctx.device, &mut encoder);
     ctx.queue.submit([encoder.finish()]);
+    (sum.read(&ctx.device), moments.read(&ctx.device))
+}
 
-    println!(
-        "Img size: {}, {}",
-        save_info.tex_width(),
-        save_info.tex_height()
-    );
-    println!("Samples: {}", samples * renderer.globals().samples);
-    save_info.finish(&ctx.device, samples);
-
-    println!("Time took: {}s", time.elapsed().as_secs())
+fn read_aov_sums(ctx: &WgpuContext, renderer: &Renderer) -> Option<[image::Rgba32FImage; 4]> {
+    let mut encoder = ctx
+        .device
+        .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Encoder"),
+        });
+    let sums = renderer.start_save_aovs(&ctx.device, &mut encoder)?;
+    ctx.queue.submit([encoder.finish()]);
+    Some(sums.map(|info| info.read(&ctx.device)))
 }
 
 pub struct WgpuContext {
@@ -90,7 +458,7 @@ pub struct WgpuContext {
     pub queue: wgpu::Queue,
 }
 impl WgpuContext {
-    pub async fn new() -> Self {
+    pub async fn new() -> Option<Self> {
         // wgpu instance
         let instance = wgpu::Instance::new(wgpu::Backends::VULKAN);
 
@@ -101,27 +469,27 @@ impl WgpuContext {
                 compatible_surface: None,
                 force_fallback_adapter: false,
             })
-            .await
-            .unwrap();
+            .await?;
 
         // device and queue
         let (device, queue) = adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
                     features: wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES,
-                    limits: wgpu::Limits::default(),
+                    // the scene needs more storage buffers than the default limit allows
+                    limits: adapter.limits(),
                     label: None,
                 },
                 None,
             )
             .await
-            .unwrap();
+            .ok()?;
 
-        Self {
+        Some(Self {
             instance,
             adapter,
             device,
             queue,
-        }
+        })
     }
 }
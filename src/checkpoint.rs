@@ -0,0 +1,394 @@
+use std::{
+    io::{Read, Seek, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::renderer::{aov::AovImages, scene::Scene, Settings};
+
+const MAGIC: &[u8; 8] = b"PTCKPT04";
+
+/// Everything needed to continue an interrupted offline render.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct CheckpointHeader {
+    /// [`scene_hash`] of the scene being rendered
+    pub scene_hash: u64,
+    pub settings: Settings,
+    pub seed: u64,
+    pub width: u32,
+    pub height: u32,
+    pub tile_size: u32,
+    /// pixels rendered around every tile for the denoiser and cropped away after
+    pub guard: u32,
+    /// index of the tile being rendered, the ones before it are finished
+    pub tile: usize,
+    /// frames accumulated in the current tile
+    pub samples: i32,
+    /// whether aov files are written, so the aovs are kept next to every tile
+    pub aovs: bool,
+}
+
+/// A checkpoint file is the magic, the length of the ron header as a little endian u64,
+/// the header and then little endian floats for the current tile, its moments and its aov sums.
+/// Finished tiles are in a second file, see [`Checkpoint::save_tile`].
+pub struct Checkpoint {
+    pub header: CheckpointHeader,
+    /// averaged finished tiles, the rest is black
+    pub frame: image::Rgba32FImage,
+    /// raw sums of the current tile and its guard band
+    pub tile: image::Rgba32FImage,
+    /// raw sums of the current tile's squared colours
+    pub moments: image::Rgba32FImage,
+    /// averaged aovs of the finished tiles, when the header has aovs
+    pub aov_frame: Option<AovImages>,
+    /// raw sums of the current tile's albedo, normal, position and id
+    pub aov_sums: Option<[image::Rgba32FImage; 4]>,
+}
+impl Checkpoint {
+    /// Writes to a temporary file first so an interruption never leaves a broken checkpoint.
+    pub fn save<P: AsRef<Path>>(
+        path: P,
+        header: &CheckpointHeader,
+        tile: &image::Rgba32FImage,
+        moments: &image::Rgba32FImage,
+        aov_sums: Option<&[image::Rgba32FImage; 4]>,
+    ) -> std::io::Result<()> {
+        let path = path.as_ref();
+        let header = ron::to_string(header).map_err(invalid)?;
+
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        {
+            let mut file = std::io::BufWriter::new(std::fs::File::create(&tmp)?);
+            file.write_all(MAGIC)?;
+            file.write_all(&(header.len() as u64).to_le_bytes())?;
+            file.write_all(header.as_bytes())?;
+            write_floats(&mut file, tile)?;
+            write_floats(&mut file, moments)?;
+            for sum in aov_sums.into_iter().flatten() {
+                write_floats(&mut file, sum)?;
+            }
+            file.flush()?;
+        }
+        std::fs::rename(tmp, path)
+    }
+
+    /// Appends a finished tile to the tiles file of the checkpoint at path, so every tile is
+    /// only written once. Whatever an interrupted write left after the tiles before it is cut off.
+    ///
+    /// Every tile is its colour followed by its aovs when the header has them.
+    pub fn save_tile<P: AsRef<Path>>(
+        path: P,
+        tiles: &[([u32; 2], [u32; 2])],
+        index: usize,
+        tile: &image::Rgba32FImage,
+        aovs: Option<&AovImages>,
+    ) -> std::io::Result<()> {
+        let images = if aovs.is_some() { 5 } else { 1 };
+        let start = tiles_len(&tiles[..index], images);
+
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(tiles_path(path))?;
+        file.set_len(start)?;
+        file.seek(std::io::SeekFrom::Start(start))?;
+        write_floats(&mut file, tile)?;
+        if let Some(aovs) = aovs {
+            for image in [&aovs.albedo, &aovs.normal, &aovs.position, &aovs.id] {
+                write_floats(&mut file, image)?;
+            }
+        }
+        file.sync_data()
+    }
+
+    /// removes the checkpoint and its tiles
+    pub fn remove<P: AsRef<Path>>(path: P) {
+        let _ = std::fs::remove_file(tiles_path(&path));
+        let _ = std::fs::remove_file(path);
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let path = path.as_ref();
+        let mut file = std::io::BufReader::new(std::fs::File::open(path)?);
+        let size = file.get_ref().metadata()?.len();
+
+        let mut magic = [0u8; 8];
+        file.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a checkpoint"));
+        }
+        let mut len = [0u8; 8];
+        file.read_exact(&mut len)?;
+        // a broken length shouldn't allocate more than the file has
+        let len = u64::from_le_bytes(len);
+        if len > size {
+            return Err(invalid("header length"));
+        }
+        let mut header = vec![0u8; len as usize];
+        file.read_exact(&mut header)?;
+        let header: CheckpointHeader = ron::de::from_bytes(&header).map_err(invalid)?;
+        if header.tile_size == 0 {
+            return Err(invalid("tile size"));
+        }
+
+        let images = if header.aovs { 5 } else { 1 };
+        let [tile_width, tile_height] = header.tile_dimensions();
+        let current = tiles_len(&[([0, 0], [tile_width, tile_height])], images + 1);
+        if MAGIC.len() as u64 + 8 + len + current != size {
+            return Err(invalid("checkpoint size"));
+        }
+        let tile = read_image(&mut file, tile_width, tile_height)?;
+        let moments = read_image(&mut file, tile_width, tile_height)?;
+        let aov_sums = if header.aovs {
+            Some(read_aovs(&mut file, tile_width, tile_height)?)
+        } else {
+            None
+        };
+
+        let mut frame = image::Rgba32FImage::new(header.width, header.height);
+        let mut aov_frame = header
+            .aovs
+            .then(|| AovImages::new(header.width, header.height));
+        if header.tile > 0 {
+            let file = std::fs::File::open(tiles_path(path))?;
+            // interrupted writes may have left more, but never less
+            let tiles = header.tiles();
+            let finished = &tiles[..header.tile.min(tiles.len())];
+            if file.metadata()?.len() < tiles_len(finished, images) {
+                return Err(invalid("missing tiles"));
+            }
+            let mut file = std::io::BufReader::new(file);
+            for &(offset, [width, height]) in header.tiles().iter().take(header.tile) {
+                let tile = read_image(&mut file, width, height)?;
+                image::imageops::replace(&mut frame, &tile, offset[0] as i64, offset[1] as i64);
+                if let Some(aov_frame) = &mut aov_frame {
+                    let [albedo, normal, position, id] = read_aovs(&mut file, width, height)?;
+                    let tile = AovImages {
+                        albedo,
+                        normal,
+                        position,
+                        id,
+                    };
+                    aov_frame.replace(&tile, offset);
+                }
+            }
+        }
+
+        Ok(Self {
+            header,
+            frame,
+            tile,
+            moments,
+            aov_frame,
+            aov_sums,
+        })
+    }
+}
+impl CheckpointHeader {
+    /// offset and size of every tile, row by row
+    pub fn tiles(&self) -> Vec<([u32; 2], [u32; 2])> {
+        tiles(self.width, self.height, self.tile_size)
+    }
+
+    /// size of the current tile with its guard band
+    fn tile_dimensions(&self) -> [u32; 2] {
+        self.tiles().get(self.tile).map_or([0, 0], |&tile| {
+            guarded(tile, self.guard, self.width, self.height).1
+        })
+    }
+}
+
+fn read_image(
+    file: &mut impl Read,
+    width: u32,
+    height: u32,
+) -> std::io::Result<image::Rgba32FImage> {
+    let mut floats = vec![0.0f32; width as usize * height as usize * 4];
+    file.read_exact(bytemuck::cast_slice_mut(&mut floats))?;
+    if cfg!(target_endian = "big") {
+        for f in &mut floats {
+            *f = f32::from_bits(u32::from_le(f.to_bits()));
+        }
+    }
+    image::Rgba32FImage::from_raw(width, height, floats).ok_or_else(|| invalid("image size"))
+}
+
+/// little endian, which is how almost every machine has them in memory already
+fn write_floats(file: &mut impl Write, image: &image::Rgba32FImage) -> std::io::Result<()> {
+    if cfg!(target_endian = "little") {
+        return file.write_all(bytemuck::cast_slice(image.as_raw()));
+    }
+    let bytes: Vec<u8> = image
+        .as_raw()
+        .iter()
+        .flat_map(|f| f.to_le_bytes())
+        .collect();
+    file.write_all(&bytes)
+}
+
+/// bytes taken by tiles of this many images each
+fn tiles_len(tiles: &[([u32; 2], [u32; 2])], images: u64) -> u64 {
+    tiles
+        .iter()
+        .map(|&(_, [w, h])| w as u64 * h as u64 * 16 * images)
+        .sum()
+}
+
+fn read_aovs(
+    file: &mut impl Read,
+    width: u32,
+    height: u32,
+) -> std::io::Result<[image::Rgba32FImage; 4]> {
+    Ok([
+        read_image(file, width, height)?,
+        read_image(file, width, height)?,
+        read_image(file, width, height)?,
+        read_image(file, width, height)?,
+    ])
+}
+
+/// finished tiles of the checkpoint at path
+fn tiles_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    let mut path = path.as_ref().as_os_str().to_owned();
+    path.push(".tiles");
+    path.into()
+}
+
+fn invalid<E>(e: E) -> std::io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    std::io::Error::new(std::io::ErrorKind::InvalidData, e)
+}
+
+/// offset and size of every tile of a frame, row by row
+pub fn tiles(width: u32, height: u32, tile_size: u32) -> Vec<([u32; 2], [u32; 2])> {
+    (0..height)
+        .step_by(tile_size as usize)
+        .flat_map(|y| {
+            (0..width).step_by(tile_size as usize).map(move |x| {
+                (
+                    [x, y],
+                    [tile_size.min(width - x), tile_size.min(height - y)],
+                )
+            })
+        })
+        .collect()
+}
+
+/// Grows a tile by guard pixels on every side, without leaving the frame.
+pub fn guarded(
+    (offset, size): ([u32; 2], [u32; 2]),
+    guard: u32,
+    width: u32,
+    height: u32,
+) -> ([u32; 2], [u32; 2]) {
+    let start = [
+        offset[0].saturating_sub(guard),
+        offset[1].saturating_sub(guard),
+    ];
+    let end = [
+        (offset[0] + size[0]).saturating_add(guard).min(width),
+        (offset[1] + size[1]).saturating_add(guard).min(height),
+    ];
+    (start, [end[0] - start[0], end[1] - start[1]])
+}
+
+/// FNV-1a of the scene written as ron, to notice a checkpoint being resumed with another scene
+pub fn scene_hash(scene: &Scene) -> u64 {
+    let ron = ron::to_string(scene).unwrap_or_default();
+    ron.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let settings: Settings = ron::from_str("(samples: 1, depth: 50)").unwrap();
+        let header = CheckpointHeader {
+            scene_hash: scene_hash(&Scene::default()),
+            settings,
+            seed: 42,
+            width: 5,
+            height: 3,
+            tile_size: 2,
+            guard: 1,
+            tile: 5,
+            samples: 7,
+            aovs: true,
+        };
+        let frame =
+            image::Rgba32FImage::from_fn(5, 3, |x, y| image::Rgba([x as f32, y as f32, 0.5, 1.0]));
+        // the last tile is the 1x1 corner, 2x2 with its guard band
+        let tile = image::Rgba32FImage::from_pixel(2, 2, image::Rgba([3.0, 2.0, 1.0, 7.0]));
+        let moments = image::Rgba32FImage::from_pixel(2, 2, image::Rgba([9.0, 4.0, 1.0, 7.0]));
+
+        let path = std::env::temp_dir().join(format!("checkpoint_test_{}", std::process::id()));
+        let aov_sums = [1.0, 2.0, 3.0, 4.0]
+            .map(|v| image::Rgba32FImage::from_pixel(2, 2, image::Rgba([v; 4])));
+        let mut aov_frame = AovImages::new(5, 3);
+        aov_frame.normal =
+            image::Rgba32FImage::from_fn(5, 3, |x, _| image::Rgba([0.0, x as f32, 0.0, 1.0]));
+
+        let tiles = header.tiles();
+        for (i, &(offset, [w, h])) in tiles.iter().enumerate().take(5) {
+            let finished = aov_frame.crop(offset, [w, h]);
+            // an interrupted write of the tile is cut off again
+            if i == 2 {
+                let broken = image::Rgba32FImage::new(1, 1);
+                Checkpoint::save_tile(&path, &tiles, i, &broken, Some(&finished)).unwrap();
+            }
+            let tile = image::imageops::crop_imm(&frame, offset[0], offset[1], w, h).to_image();
+            Checkpoint::save_tile(&path, &tiles, i, &tile, Some(&finished)).unwrap();
+        }
+        Checkpoint::save(&path, &header, &tile, &moments, Some(&aov_sums)).unwrap();
+        let loaded = Checkpoint::load(&path).unwrap();
+        Checkpoint::remove(&path);
+        assert!(!tiles_path(&path).exists());
+
+        let mut frame = frame;
+        frame.put_pixel(4, 2, image::Rgba([0.0; 4]));
+        aov_frame.normal.put_pixel(4, 2, image::Rgba([0.0; 4]));
+        assert_eq!(loaded.aov_frame.unwrap().normal, aov_frame.normal);
+        assert_eq!(loaded.aov_sums, Some(aov_sums));
+
+        assert_eq!(loaded.header.scene_hash, header.scene_hash);
+        assert_eq!(loaded.header.seed, 42);
+        assert_eq!(loaded.header.samples, 7);
+        assert_eq!(loaded.frame, frame);
+        assert_eq!(loaded.tile, tile);
+        assert_eq!(loaded.moments, moments);
+    }
+
+    #[test]
+    fn broken_lengths_are_rejected() {
+        let path = std::env::temp_dir().join(format!("checkpoint_broken_{}", std::process::id()));
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(u64::MAX.to_le_bytes());
+        std::fs::write(&path, bytes).unwrap();
+        let error = Checkpoint::load(&path).err().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn tiles_cover_the_frame() {
+        let tiles = tiles(5, 3, 2);
+        assert_eq!(tiles.len(), 6);
+        let area: u32 = tiles.iter().map(|(_, [w, h])| w * h).sum();
+        assert_eq!(area, 15);
+        assert_eq!(tiles[5], ([4, 2], [1, 1]));
+    }
+
+    #[test]
+    fn guard_bands_stay_in_the_frame() {
+        assert_eq!(guarded(([2, 0], [2, 2]), 1, 5, 3), ([1, 0], [4, 3]));
+        assert_eq!(guarded(([4, 2], [1, 1]), 8, 5, 3), ([0, 0], [5, 3]));
+        assert_eq!(guarded(([0, 0], [5, 3]), 0, 5, 3), ([0, 0], [5, 3]));
+    }
+}
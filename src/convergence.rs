@@ -0,0 +1,96 @@
+/// What ended an offline render.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    /// every requested frame was rendered
+    Samples,
+    TimeBudget,
+    NoiseThreshold,
+}
+impl std::fmt::Display for StopReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            StopReason::Samples => "sample count",
+            StopReason::TimeBudget => "time budget",
+            StopReason::NoiseThreshold => "noise threshold",
+        })
+    }
+}
+
+/// keeps nearly black pixels from dominating the relative variance
+const DARK_BIAS: f32 = 1e-3;
+/// the image is as noisy as the pixel this far up, so a noisy corner isn't averaged away
+/// by a converged background while a few stray fireflies don't keep the render going
+const NOISE_PERCENTILE: f32 = 0.99;
+
+/// Estimates how noisy an accumulated image still is from the raw sums of every frame's
+/// colour and of every frame's squared colour, the alpha of `sum` is every pixel's frame count.
+///
+/// Every pixel's variance of the mean is divided by the squared mean and the worst channel
+/// is taken, the result is the 99th percentile of that over the image.
+/// src/renderer/compute.wgsl has the same estimate per pixel for adaptive sampling.
+pub fn relative_variance(sum: &image::Rgba32FImage, moments: &image::Rgba32FImage) -> f32 {
+    if sum.width() == 0 || sum.height() == 0 {
+        return f32::INFINITY;
+    }
+
+    let mut pixels: Vec<f32> = sum
+        .pixels()
+        .zip(moments.pixels())
+        .map(|(s, m)| {
+            let n = s[3];
+            // one frame says nothing about the variance
+            if n < 2.0 {
+                return f32::INFINITY;
+            }
+            (0..3)
+                .map(|c| {
+                    let mean = s[c] / n;
+                    // unbiased sample variance of a frame, then of the mean
+                    let variance = (m[c] / n - mean * mean).max(0.0) * n / (n - 1.0);
+                    variance / n / (mean * mean + DARK_BIAS)
+                })
+                .fold(0.0, f32::max)
+        })
+        .collect();
+
+    let rank = (pixels.len() as f32 * NOISE_PERCENTILE).ceil() as usize - 1;
+    *pixels.select_nth_unstable_by(rank, f32::total_cmp).1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn constant_frames_have_no_noise() {
+        let sum = image::Rgba32FImage::from_pixel(4, 4, image::Rgba([2.0, 4.0, 8.0, 4.0]));
+        let moments = image::Rgba32FImage::from_pixel(4, 4, image::Rgba([1.0, 4.0, 16.0, 0.0]));
+        assert_eq!(relative_variance(&sum, &moments), 0.0);
+    }
+
+    #[test]
+    fn noise_falls_with_more_frames() {
+        // frames alternating between 0 and 2 have a mean of 1 and a variance of 1
+        let variance = |frames: i32| {
+            let sum = image::Rgba32FImage::from_pixel(1, 1, image::Rgba([frames as f32; 4]));
+            let moments =
+                image::Rgba32FImage::from_pixel(1, 1, image::Rgba([2.0 * frames as f32; 4]));
+            relative_variance(&sum, &moments)
+        };
+        assert!(variance(1).is_infinite());
+        let expected = 4.0 / 3.0 / 4.0 / (1.0 + DARK_BIAS);
+        assert!((variance(4) - expected).abs() < 1e-6);
+        assert!(variance(100) < variance(10));
+    }
+
+    #[test]
+    fn one_noisy_pixel_is_not_averaged_away() {
+        // 4 frames of a flat image, one pixel alternates between 0 and 2
+        let sum = image::Rgba32FImage::from_pixel(8, 8, image::Rgba([4.0; 4]));
+        let mut moments = image::Rgba32FImage::from_pixel(8, 8, image::Rgba([4.0; 4]));
+        moments.put_pixel(3, 5, image::Rgba([8.0, 8.0, 8.0, 0.0]));
+
+        let expected = 4.0 / 3.0 / 4.0 / (1.0 + DARK_BIAS);
+        assert!((relative_variance(&sum, &moments) - expected).abs() < 1e-6);
+    }
+}
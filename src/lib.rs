@@ -1,3 +1,5 @@
+pub mod checkpoint;
+pub mod convergence;
 pub mod model;
 pub mod render_pipeline;
 pub mod renderer;
@@ -12,6 +14,132 @@ where
 
     ron::from_str::<T>(&content).ok()
 }
+/// Loads a scene from a .ron, .gltf or .glb file
+pub fn load_scene<P>(path: P) -> Option<renderer::scene::Scene>
+where
+    P: AsRef<std::path::Path>,
+{
+    let path = path.as_ref();
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("gltf" | "glb") => match renderer::gltf::load(path) {
+            Ok(scene) => Some(scene),
+            Err(e) => {
+                log::error!("{}: {e}", path.display());
+                None
+            }
+        },
+        _ => load_ron(path),
+    }
+}
+/// Saves a linear image, picking the format from the extension.
+/// .exr and .hdr keep the floats as they are, anything else is tone mapped to 8 bit srgb.
+pub fn save_image<P>(
+    path: P,
+    img: &image::Rgba32FImage,
+    tone_mapping: &renderer::tonemap::ToneMapping,
+) -> image::ImageResult<()>
+where
+    P: AsRef<std::path::Path>,
+{
+    let path = path.as_ref();
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("exr") => img.save(path),
+        Some("hdr") => {
+            // radiance hdr has no alpha
+            let pixels: Vec<image::Rgb<f32>> = img
+                .pixels()
+                .map(|p| image::Rgb([p[0], p[1], p[2]]))
+                .collect();
+            let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+            image::codecs::hdr::HdrEncoder::new(file).encode(
+                &pixels,
+                img.width() as usize,
+                img.height() as usize,
+            )
+        }
+        _ => {
+            let img = image::RgbImage::from_fn(img.width(), img.height(), |x, y| {
+                let p = img.get_pixel(x, y);
+                let rgb = tone_mapping.map([p[0], p[1], p[2]]);
+                image::Rgb(rgb.map(|c| (renderer::tonemap::srgb_oetf(c) * 255.0).round() as u8))
+            });
+            img.save(path)
+        }
+    }
+}
+/// Saves the aovs next to `path` as `{stem}.{aov}.{ext}`, one image for the albedo, normal,
+/// position, depth and id. .exr and .hdr keep the floats, with .exr being exact for the ids,
+/// anything else gets an 8 bit visualisation.
+pub fn save_aovs<P>(path: P, aovs: &renderer::aov::AovImages) -> image::ImageResult<()>
+where
+    P: AsRef<std::path::Path>,
+{
+    let path = path.as_ref();
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let ext = path
+        .extension()
+        .map(|e| e.to_string_lossy())
+        .unwrap_or_else(|| "png".into());
+    let aov_path = |name: &str| path.with_file_name(format!("{stem}.{name}.{ext}"));
+
+    let depth =
+        image::Rgba32FImage::from_fn(aovs.position.width(), aovs.position.height(), |x, y| {
+            let d = aovs.position.get_pixel(x, y)[3];
+            image::Rgba([d, d, d, 1.0])
+        });
+
+    if matches!(&*ext, "exr" | "hdr") {
+        let tone_mapping = renderer::tonemap::ToneMapping::default();
+        for (name, img) in [
+            ("albedo", &aovs.albedo),
+            ("normal", &aovs.normal),
+            ("position", &aovs.position),
+            ("depth", &depth),
+            ("id", &aovs.id),
+        ] {
+            save_image(aov_path(name), img, &tone_mapping)?;
+        }
+        return Ok(());
+    }
+
+    let to_8bit = |img: &image::Rgba32FImage, f: &dyn Fn([f32; 3]) -> [f32; 3]| {
+        image::RgbImage::from_fn(img.width(), img.height(), |x, y| {
+            let p = img.get_pixel(x, y);
+            image::Rgb(f([p[0], p[1], p[2]]).map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8))
+        })
+    };
+    // the ranges of the position and depth are stretched to fill the image
+    let range = |img: &image::Rgba32FImage, c: usize| {
+        img.pixels().fold((f32::MAX, f32::MIN), |(min, max), p| {
+            (min.min(p[c]), max.max(p[c]))
+        })
+    };
+    let ranges = [0, 1, 2].map(|c| range(&aovs.position, c));
+    let (_, max_depth) = range(&depth, 0);
+
+    to_8bit(&aovs.albedo, &|rgb| rgb.map(renderer::tonemap::srgb_oetf)).save(aov_path("albedo"))?;
+    to_8bit(&aovs.normal, &|n| n.map(|c| c * 0.5 + 0.5)).save(aov_path("normal"))?;
+    to_8bit(&aovs.position, &|p| {
+        [0, 1, 2].map(|c| {
+            let (min, max) = ranges[c];
+            (p[c] - min) / (max - min).max(f32::EPSILON)
+        })
+    })
+    .save(aov_path("position"))?;
+    to_8bit(&depth, &|d| d.map(|d| d / max_depth.max(f32::EPSILON))).save(aov_path("depth"))?;
+    // every object gets its own colour, misses are black
+    to_8bit(&aovs.id, &|[prim_type, prim_index, _]| {
+        if prim_type == 0.0 {
+            return [0.0; 3];
+        }
+        let hash = (prim_type as u32)
+            .wrapping_mul(0x9e37_79b9)
+            .wrapping_add((prim_index as u32).wrapping_mul(0x85eb_ca6b));
+        let hash = (hash ^ (hash >> 15)).wrapping_mul(0x2c1b_3c6d);
+        [0, 8, 16].map(|shift| ((hash >> shift) & 0xff) as f32 / 255.0)
+    })
+    .save(aov_path("id"))
+}
 pub fn save_ron<P, T>(path: P, value: &T)
 where
     P: AsRef<std::path::Path>,
@@ -2,7 +2,7 @@
 
 use model::*;
 use pathtracer::{
-    renderer::{bytes::Bytes, vector3::*},
+    renderer::{bytes::Bytes, denoiser::Denoiser, vector3::*},
     *,
 };
 use render_pipeline::*;
@@ -13,7 +13,8 @@ use wgpu_context::*;
 
 use winit::{
     event::{
-        DeviceEvent, ElementState, Event, KeyboardInput, MouseButton, VirtualKeyCode, WindowEvent,
+        DeviceEvent, ElementState, Event, KeyboardInput, MouseButton, MouseScrollDelta,
+        VirtualKeyCode, WindowEvent,
     },
     event_loop::{ControlFlow, EventLoop},
     window::{CursorGrabMode, Window, WindowBuilder},
@@ -22,6 +23,11 @@ use winit::{
 const VERTICIES: [[f32; 2]; 4] = [[-1.0, 1.0], [1.0, 1.0], [1.0, -1.0], [-1.0, -1.0]];
 const INDECIES: [u16; 6] = [0, 1, 2, 2, 3, 0];
 
+/// aperture change per key press
+const APERTURE_STEP: f32 = 0.05;
+/// focus distance is scaled by this per scroll line
+const FOCUS_SCALE: f32 = 1.1;
+
 #[derive(Clone, Copy, Debug)]
 struct CameraController {
     forward: bool,
@@ -63,7 +69,13 @@ struct App {
     model: Model,
     sampler: wgpu::Sampler,
     camera_controller: CameraController,
-    save_next_frame: bool,
+    /// where to save the next frame
+    save_next_frame: Option<&'static str>,
+    /// shows how many samples every pixel got
+    heatmap: bool,
+    denoiser: Denoiser,
+    /// shows the denoised image
+    denoise: bool,
 }
 impl App {
     async fn new(window: &Window) -> Self {
@@ -83,17 +95,21 @@ impl App {
             .device
             .create_sampler(&wgpu::SamplerDescriptor::default());
 
-        // load scene
-        let scene: Scene = load_ron("scene1.ron").unwrap_or_default();
+        // load scene, a .ron, .gltf or .glb path can be given as the first argument
+        let scene_path = std::env::args()
+            .nth(1)
+            .unwrap_or_else(|| "scene1.ron".to_string());
+        let scene: Scene = load_scene(scene_path).unwrap_or_default();
         // load settings
         let settings: Settings = load_ron("settings.ron").unwrap();
 
-        let renderer = Renderer::new(&ctx.device, scene, settings, width, height);
+        let renderer = Renderer::new(&ctx.device, &ctx.queue, scene, settings, width, height);
+        let denoiser = Denoiser::new(&ctx.device, settings.denoiser);
 
         // camera controller for real time
         let camera_controller = CameraController::new();
 
-        let save_next_frame = false;
+        let save_next_frame = None;
 
         Self {
             ctx,
@@ -103,17 +119,27 @@ impl App {
             sampler,
             camera_controller,
             save_next_frame,
+            heatmap: false,
+            denoiser,
+            denoise: false,
         }
     }
 
     fn reload_scene(&mut self, c: char) {
-        if let Some(scene) = load_ron(format!("scene{}.ron", c)) {
-            self.renderer.reload_scene(&self.ctx.device, scene);
+        let path = ["ron", "gltf", "glb"]
+            .map(|ext| format!("scene{}.{}", c, ext))
+            .into_iter()
+            .find(|path| std::path::Path::new(path).exists());
+
+        if let Some(scene) = path.and_then(load_scene) {
+            self.renderer
+                .reload_scene(&self.ctx.device, &self.ctx.queue, scene);
         }
     }
     fn reload_settings(&mut self) {
         let settings: Settings = load_ron("settings.ron").unwrap();
         self.renderer.reload_settings(&settings);
+        self.denoiser.reload_settings(settings.denoiser);
     }
 
     fn render(&mut self) {
@@ -147,21 +173,28 @@ impl App {
 
         // accumulate one frame
         self.renderer.render(&self.ctx.device, &mut encoder);
+        if self.denoise {
+            self.denoiser
+                .denoise(&self.ctx.device, &mut encoder, &self.renderer);
+        }
         // draw accumulated texture
         self.render_pass(&mut encoder, &view);
 
-        let mut save_info = None;
-        if self.save_next_frame {
-            save_info = Some(self.renderer.start_save(&self.ctx.device, &mut encoder));
-            self.save_next_frame = false;
-        }
+        let save_info = self.save_next_frame.take().map(|path| {
+            let save_info = if self.denoise {
+                self.denoiser.start_save(&self.ctx.device, &mut encoder)
+            } else {
+                self.renderer.start_save(&self.ctx.device, &mut encoder)
+            };
+            (path, save_info)
+        });
 
         // finish frame
         self.ctx.queue.submit([encoder.finish()]);
         self.ctx.device.poll(wgpu::Maintain::Wait);
         output.present();
 
-        if let Some(save_info) = save_info {
+        if let Some((path, save_info)) = save_info {
             println!(
                 "Img size: {}, {}",
                 save_info.tex_width(),
@@ -171,17 +204,33 @@ impl App {
                 "Samples: {}",
                 self.renderer.samples() * self.renderer.globals().samples
             );
-            save_info.finish(&self.ctx.device, self.renderer.samples());
+            let img = save_info.finish(&self.ctx.device, self.renderer.outlier_rejection());
+            if let Err(e) = save_image(path, &img, &self.renderer.tone_mapping()) {
+                log::error!("{path}: {e}");
+            }
         }
     }
 
     fn render_pass(&mut self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) {
+        let texture = if self.denoise {
+            self.denoiser.texture()
+        } else {
+            self.renderer.texture()
+        };
         let globals = self
             .ctx
             .device
             .create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some("Render Globals"),
-                contents: &self.renderer.samples().bytes(),
+                contents: &self
+                    .renderer
+                    .tone_mapping()
+                    .display(
+                        self.renderer.samples() * self.renderer.globals().samples,
+                        !self.ctx.surface_config.format.describe().srgb,
+                        self.heatmap,
+                    )
+                    .bytes(),
                 usage: wgpu::BufferUsages::UNIFORM,
             });
 
@@ -194,9 +243,7 @@ impl App {
                 entries: &[
                     wgpu::BindGroupEntry {
                         binding: 0,
-                        resource: wgpu::BindingResource::TextureView(
-                            self.renderer.texture().view(),
-                        ),
+                        resource: wgpu::BindingResource::TextureView(texture.view()),
                     },
                     wgpu::BindGroupEntry {
                         binding: 1,
@@ -210,6 +257,12 @@ impl App {
                             size: None,
                         }),
                     },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: wgpu::BindingResource::TextureView(
+                            self.renderer.moments().view(),
+                        ),
+                    },
                 ],
             });
         {
@@ -284,11 +337,35 @@ impl App {
         if let ElementState::Pressed = state {
             match key {
                 VirtualKeyCode::Z => {
-                    self.save_next_frame = true;
+                    self.save_next_frame = Some("img.png");
+                }
+                VirtualKeyCode::X => {
+                    self.save_next_frame = Some("img.exr");
                 }
                 VirtualKeyCode::F => {
                     self.reload_settings();
                 }
+                VirtualKeyCode::H => {
+                    self.heatmap = !self.heatmap;
+                }
+                VirtualKeyCode::N => {
+                    self.denoise = !self.denoise;
+                    self.renderer.set_denoising(self.denoise);
+                }
+                VirtualKeyCode::T => {
+                    let temporal = !self.renderer.temporal();
+                    self.renderer.set_temporal(temporal);
+                }
+                VirtualKeyCode::LBracket | VirtualKeyCode::RBracket => {
+                    let step = if *key == VirtualKeyCode::LBracket {
+                        -APERTURE_STEP
+                    } else {
+                        APERTURE_STEP
+                    };
+                    let camera_config = self.renderer.camera_config_mut();
+                    camera_config.aperture = (camera_config.aperture + step).max(0.0);
+                    println!("Aperture: {:.2}", camera_config.aperture);
+                }
                 &c => {
                     if c >= VirtualKeyCode::Key1 && c <= VirtualKeyCode::Key0 {
                         self.reload_scene(char::from_digit(c as u32 + 1, 9).unwrap());
@@ -298,6 +375,13 @@ impl App {
         }
     }
 
+    fn scroll(&mut self, lines: f32) {
+        let camera_config = self.renderer.camera_config_mut();
+        camera_config.focus_distance =
+            (camera_config.focus_distance * FOCUS_SCALE.powf(lines)).max(0.01);
+        println!("Focus distance: {:.2}", camera_config.focus_distance);
+    }
+
     fn update(&mut self, dt: f32) {
         let sensitivity = 0.5; // mouse sensitivity
 
@@ -424,6 +508,14 @@ fn main() {
                         }
                     };
                 }
+                WindowEvent::MouseWheel { delta, .. } => {
+                    let lines = match delta {
+                        MouseScrollDelta::LineDelta(_, y) => y,
+                        // roughly how many pixels a line is
+                        MouseScrollDelta::PixelDelta(pos) => pos.y as f32 / 20.0,
+                    };
+                    app.scroll(lines);
+                }
                 _ => {}
             },
             Event::DeviceEvent {
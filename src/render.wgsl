@@ -19,24 +19,102 @@ fn vs_main(model: VertexIn) -> VertexOut {
 var tex: texture_2d<f32>;
 @group(0) @binding(1)
 var sam: sampler;
+struct Display {
+    samples: i32,
+    // linear scale
+    exposure: f32,
+    // 0 clamp, 1 reinhard, 2 aces, 3 agx
+    tone_mapper: u32,
+    encode_srgb: u32,
+    // shows how many samples every pixel got instead of the image
+    heatmap: u32,
+}
+
 @group(0) @binding(2)
-var<uniform> samples: i32;
+var<uniform> display: Display;
+// alpha is how many samples the pixel got
+@group(0) @binding(3)
+var moments: texture_2d<f32>;
 
-// srgb -> linear
-fn correct(colour: vec3<f32>) -> vec3<f32> {
-    let exp = 2.2;
+// the same operators as src/renderer/tonemap.rs, keep them the same
 
-    return vec3<f32>(
-        pow(colour.x, exp),
-        pow(colour.y, exp),
-        pow(colour.z, exp),
+// Narkowicz's fit of the ACES filmic curve
+fn aces(x: vec3<f32>) -> vec3<f32> {
+    return (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14);
+}
+// minimal AgX approximation
+fn agx(colour: vec3<f32>) -> vec3<f32> {
+    let inset = mat3x3<f32>(
+        vec3<f32>(0.842479062253094, 0.0423282422610123, 0.0423756549057051),
+        vec3<f32>(0.0784335999999992, 0.878468636469772, 0.0784336),
+        vec3<f32>(0.0792237451477643, 0.0791661274605434, 0.879142973793104),
+    );
+    let outset = mat3x3<f32>(
+        vec3<f32>(1.19687900512017, -0.0528968517574562, -0.0529716355144438),
+        vec3<f32>(-0.0980208811401368, 1.15190312990417, -0.0980434501171241),
+        vec3<f32>(-0.0990297440797205, -0.0989611768448433, 1.15107367264116),
+    );
+    let min_ev = -12.47393;
+    let max_ev = 4.026069;
+
+    // log encoding of the inset colour
+    let ev = clamp(log2(max(inset * colour, vec3<f32>(1e-10))), vec3<f32>(min_ev), vec3<f32>(max_ev));
+    let x = (ev - min_ev) / (max_ev - min_ev);
+
+    // sigmoid contrast curve
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    let curve = 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232;
+
+    return pow(max(outset * curve, vec3<f32>(0.0)), vec3<f32>(2.2));
+}
+fn tone_map(colour: vec3<f32>) -> vec3<f32> {
+    let x = max(colour * display.exposure, vec3<f32>(0.0));
+    var mapped: vec3<f32>;
+    switch display.tone_mapper {
+        case 1u {
+            mapped = x / (1.0 + x);
+        }
+        case 2u {
+            mapped = aces(x);
+        }
+        case 3u {
+            mapped = agx(x);
+        }
+        default {
+            mapped = x;
+        }
+    }
+    return clamp(mapped, vec3<f32>(0.0), vec3<f32>(1.0));
+}
+// display linear -> srgb
+fn srgb_oetf(colour: vec3<f32>) -> vec3<f32> {
+    return select(
+        1.055 * pow(colour, vec3<f32>(1.0 / 2.4)) - 0.055,
+        colour * 12.92,
+        colour <= vec3<f32>(0.0031308)
     );
 }
 
+// blue for few samples to red for every sample
+fn heatmap(t: f32) -> vec3<f32> {
+    return clamp(vec3<f32>(t, 1.0 - abs(2.0 * t - 1.0), 1.0 - t), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
 @fragment
 fn fs_main(in: VertexOut) -> @location(0) vec4<f32> {
     let colour = textureSample(tex, sam, in.uv);
-    let rgb = colour.xyz / f32(samples);
-    // return vec4<f32>(correct(rgb), colour.w);
-    return vec4<f32>(rgb, colour.w);
-}
\ No newline at end of file
+    // alpha is how many frames the pixel got
+    var rgb: vec3<f32>;
+    if display.heatmap != 0u {
+        let samples = textureSample(moments, sam, in.uv).w;
+        rgb = heatmap(samples / f32(max(display.samples, 1)));
+    } else {
+        rgb = tone_map(colour.xyz / max(colour.w, 1.0));
+    }
+    // srgb surfaces encode on write
+    if display.encode_srgb != 0u {
+        rgb = srgb_oetf(rgb);
+    }
+    return vec4<f32>(rgb, 1.0);
+}
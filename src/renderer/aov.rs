@@ -0,0 +1,161 @@
+use super::texture::Texture;
+
+/// Arbitrary output variables, sums of what the camera rays hit first.
+/// The textures are 1x1 while they're turned off.
+pub struct Aovs {
+    /// albedo and the number of frames in alpha
+    pub albedo: Texture,
+    pub normal: Texture,
+    /// position and linear depth in alpha
+    pub position: Texture,
+    /// prim_type, prim_index, mat_type and mat_index of the last frame
+    pub id: Texture,
+}
+impl Aovs {
+    pub fn new(device: &wgpu::Device, width: u32, height: u32) -> Self {
+        Self {
+            albedo: Texture::new(device, width, height),
+            normal: Texture::new(device, width, height),
+            position: Texture::new(device, width, height),
+            id: Texture::new(device, width, height),
+        }
+    }
+
+    /// Only recreates the textures when the size changes.
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        let size = self.albedo.desc().size;
+        if size.width == width && size.height == height {
+            return;
+        }
+        for texture in self.textures_mut() {
+            texture.resize(device, width, height);
+        }
+    }
+
+    pub fn clear(&self, encoder: &mut wgpu::CommandEncoder) {
+        let attachments = self.textures().map(|texture| {
+            Some(wgpu::RenderPassColorAttachment {
+                view: texture.view(),
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: true,
+                },
+            })
+        });
+        let _render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("AOV Clear Pass"),
+            color_attachments: &attachments,
+            depth_stencil_attachment: None,
+        });
+    }
+
+    /// albedo, normal, position and id
+    pub fn textures(&self) -> [&Texture; 4] {
+        [&self.albedo, &self.normal, &self.position, &self.id]
+    }
+
+    fn textures_mut(&mut self) -> [&mut Texture; 4] {
+        [
+            &mut self.albedo,
+            &mut self.normal,
+            &mut self.position,
+            &mut self.id,
+        ]
+    }
+}
+
+/// AOVs read back from the gpu, everything but the id averaged over the frames.
+pub struct AovImages {
+    pub albedo: image::Rgba32FImage,
+    pub normal: image::Rgba32FImage,
+    /// linear depth in alpha
+    pub position: image::Rgba32FImage,
+    pub id: image::Rgba32FImage,
+}
+impl AovImages {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            albedo: image::Rgba32FImage::new(width, height),
+            normal: image::Rgba32FImage::new(width, height),
+            position: image::Rgba32FImage::new(width, height),
+            id: image::Rgba32FImage::new(width, height),
+        }
+    }
+
+    /// Averages the raw sums of albedo, normal, position and id, in that order.
+    pub fn from_sums([mut albedo, mut normal, mut position, id]: [image::Rgba32FImage; 4]) -> Self {
+        for ((a, n), p) in albedo
+            .pixels_mut()
+            .zip(normal.pixels_mut())
+            .zip(position.pixels_mut())
+        {
+            let frames = 1.0 / a[3].max(1.0);
+            for c in 0..3 {
+                a[c] *= frames;
+                n[c] *= frames;
+            }
+            for c in 0..4 {
+                p[c] *= frames;
+            }
+            a[3] = 1.0;
+            n[3] = 1.0;
+
+            // averaging at edges shortens the normal
+            let length = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt();
+            if length > 0.0 {
+                for c in 0..3 {
+                    n[c] /= length;
+                }
+            }
+        }
+
+        Self {
+            albedo,
+            normal,
+            position,
+            id,
+        }
+    }
+
+    /// The part of the images at offset with the given size.
+    pub fn crop(&self, offset: [u32; 2], [width, height]: [u32; 2]) -> AovImages {
+        let crop = |image: &image::Rgba32FImage| {
+            image::imageops::crop_imm(image, offset[0], offset[1], width, height).to_image()
+        };
+        AovImages {
+            albedo: crop(&self.albedo),
+            normal: crop(&self.normal),
+            position: crop(&self.position),
+            id: crop(&self.id),
+        }
+    }
+
+    /// Copies the images of a tile in at offset.
+    pub fn replace(&mut self, tile: &AovImages, offset: [u32; 2]) {
+        let (x, y) = (offset[0] as i64, offset[1] as i64);
+        image::imageops::replace(&mut self.albedo, &tile.albedo, x, y);
+        image::imageops::replace(&mut self.normal, &tile.normal, x, y);
+        image::imageops::replace(&mut self.position, &tile.position, x, y);
+        image::imageops::replace(&mut self.id, &tile.id, x, y);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sums_are_averaged() {
+        let albedo = image::Rgba32FImage::from_pixel(1, 1, image::Rgba([2.0, 1.0, 0.0, 4.0]));
+        let normal = image::Rgba32FImage::from_pixel(1, 1, image::Rgba([0.0, 3.0, 0.0, 0.0]));
+        let position = image::Rgba32FImage::from_pixel(1, 1, image::Rgba([4.0, 8.0, -4.0, 20.0]));
+        let id = image::Rgba32FImage::from_pixel(1, 1, image::Rgba([1.0, 7.0, 2.0, 3.0]));
+
+        let aovs = AovImages::from_sums([albedo, normal, position, id.clone()]);
+        assert_eq!(aovs.albedo.get_pixel(0, 0).0, [0.5, 0.25, 0.0, 1.0]);
+        assert_eq!(aovs.normal.get_pixel(0, 0).0, [0.0, 1.0, 0.0, 1.0]);
+        assert_eq!(aovs.position.get_pixel(0, 0).0, [1.0, 2.0, -1.0, 5.0]);
+        assert_eq!(aovs.id, id);
+    }
+}
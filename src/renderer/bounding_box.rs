@@ -1,6 +1,7 @@
 use super::{
     bytes::Bytes,
     sphere::Sphere,
+    triangle::{Triangle, Vertex},
     vector3::{max, min, Vector3},
 };
 
@@ -9,6 +10,31 @@ pub struct BoundingBox {
     pub min: Vector3,
     pub max: Vector3,
 }
+impl BoundingBox {
+    /// box that contains nothing, combining anything with it gives the other box
+    pub const EMPTY: Self = Self {
+        min: Vector3::new(f32::MAX, f32::MAX, f32::MAX),
+        max: Vector3::new(f32::MIN, f32::MIN, f32::MIN),
+    };
+
+    pub fn from_triangle(triangle: &Triangle, vertices: &[Vertex]) -> Self {
+        let [a, b, c] = triangle.indecies.map(|i| vertices[i as usize].pos);
+        // flat triangles would have a zero width box without padding
+        let padding = 0.01;
+        let offset = Vector3::new(padding, padding, padding);
+        let min = min(&min(&a, &b), &c) - offset;
+        let max = max(&max(&a, &b), &c) + offset;
+
+        Self { min, max }
+    }
+    pub fn centroid(&self) -> Vector3 {
+        (self.min + self.max) * 0.5
+    }
+    pub fn surface_area(&self) -> f32 {
+        let d = self.max - self.min;
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+}
 impl From<Sphere> for BoundingBox {
     fn from(value: Sphere) -> Self {
         Self::from(&value)
@@ -2,13 +2,15 @@ use super::{
     bounding_box::{self, BoundingBox},
     bytes::Bytes,
     sphere::Sphere,
+    triangle::{Triangle, Vertex},
     vector3::Vector3,
 };
 
 // https://www.ks.uiuc.edu/Research/vmd/projects/ece498/raytracing/GPU_BVHthesis.pdf
 
 const ESCAPE: u32 = 0;
-const OBJECT: u32 = 1;
+const SPHERE: u32 = 1;
+const TRIANGLE: u32 = 2;
 
 #[derive(Clone, Copy, Debug)]
 pub struct BVHNode {
@@ -17,6 +19,20 @@ pub struct BVHNode {
     pub index_type: u32,
     pub index: u32,
 }
+/// An escape node that jumps past the end of a bvh holding only itself,
+/// used as the gpu buffer of an empty scene.
+impl Default for BVHNode {
+    fn default() -> Self {
+        Self {
+            bbox: BoundingBox {
+                min: Vector3::ZERO,
+                max: Vector3::ZERO,
+            },
+            index_type: ESCAPE,
+            index: 1,
+        }
+    }
+}
 impl Bytes for BVHNode {
     fn bytes(&self) -> Vec<u8> {
         let mut b = vec![];
@@ -35,86 +51,233 @@ impl Bytes for BVHNode {
     }
 }
 
+/// leaf of the bvh, index into the spheres or triangles buffer
+#[derive(Clone, Copy, Debug)]
+pub enum Primitive {
+    Sphere(u32),
+    Triangle(u32),
+}
+
 #[derive(Clone, Debug)]
 enum Node {
-    Node(BVHTree),
-    Object((u32, BoundingBox)),
+    Node(Interior),
+    Object((Primitive, BoundingBox)),
+}
+impl Node {
+    fn bbox(&self) -> BoundingBox {
+        match self {
+            Node::Node(interior) => interior.bbox,
+            Node::Object((_, bbox)) => *bbox,
+        }
+    }
 }
 
+/// How the objects of an interior node are split between its children
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum BVHBuilder {
+    /// binned surface area heuristic, falls back to the median split
+    /// when all the centroids are in the same place
+    #[default]
+    Sah,
+    /// split at the median centroid along the longest axis
+    Median,
+}
+
+const SAH_BINS: usize = 16;
+/// cost of testing a bounding box relative to intersecting an object
+const TRAVERSAL_COST: f32 = 0.5;
+const INTERSECTION_COST: f32 = 1.0;
+
+type Objects = Vec<(Primitive, BoundingBox)>;
+
+/// Root of the bvh, an empty scene has no root
+/// and a scene with one object has that object as the root.
 #[derive(Clone, Debug)]
 pub struct BVHTree {
+    root: Option<Node>,
+}
+impl BVHTree {
+    pub fn new(
+        builder: BVHBuilder,
+        spheres: &[Sphere],
+        vertices: &[Vertex],
+        triangles: &[Triangle],
+    ) -> Self {
+        let spheres = spheres
+            .iter()
+            .enumerate()
+            .map(|(i, sphere)| (Primitive::Sphere(i as u32), sphere.into()));
+        let triangles = triangles.iter().enumerate().map(|(i, triangle)| {
+            (
+                Primitive::Triangle(i as u32),
+                BoundingBox::from_triangle(triangle, vertices),
+            )
+        });
+        let scene: Objects = spheres.chain(triangles).collect();
+
+        let root = if scene.is_empty() {
+            None
+        } else {
+            Some(Interior::new_node(builder, scene))
+        };
+        Self { root }
+    }
+
+    /// Expected cost of tracing a ray through the tree, lower is better.
+    /// The chance of a ray hitting a box is taken to be proportional to its surface area.
+    pub fn cost(&self) -> f32 {
+        match &self.root {
+            None => 0.0,
+            Some(Node::Object(_)) => INTERSECTION_COST,
+            Some(Node::Node(interior)) => interior.cost(1.0, interior.bbox.surface_area()),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Interior {
     bbox: BoundingBox,
     left: Box<Node>,
     right: Box<Node>,
 }
-impl BVHTree {
-    pub fn new(scene: Vec<Sphere>) -> Self {
-        let scene: Vec<(u32, Sphere)> = scene
-            .into_iter()
-            .enumerate()
-            .map(|us| (us.0 as u32, us.1))
-            .collect();
+impl Interior {
+    /// needs at least 2 objects
+    fn new(builder: BVHBuilder, mut scene: Objects) -> Self {
+        let scene_other = match builder {
+            BVHBuilder::Sah => split_sah(&mut scene).unwrap_or_else(|| split_median(&mut scene)),
+            BVHBuilder::Median => split_median(&mut scene),
+        };
+
+        let left = Self::new_node(builder, scene);
+        let right = Self::new_node(builder, scene_other);
+        let bbox = bounding_box::combine(&left.bbox(), &right.bbox());
 
-        Self::new_interior(scene)
-    }
-    fn new_interior(mut scene: Vec<(u32, Sphere)>) -> Self {
-        if scene.len() == 0 || scene.len() == 1 {
-            panic!("Scene length can't be 0 or 1")
-        } else if scene.len() == 2 {
-            let lhs: BoundingBox = scene[0].1.into();
-            let rhs: BoundingBox = scene[1].1.into();
-            let left = Box::new(Node::Object((scene[0].0, lhs)));
-            let right = Box::new(Node::Object((scene[1].0, rhs)));
-            let bbox = bounding_box::combine(&lhs, &rhs);
-
-            Self { bbox, left, right }
-        } else if scene.len() == 3 {
-            let obj = scene.pop().unwrap();
-
-            let bbox: BoundingBox = obj.1.into();
-            let left = Box::new(Node::Object((obj.0, bbox)));
-
-            let right = Self::new_interior(scene);
-            let bbox = bounding_box::combine(&bbox, &right.bbox);
-            let right = Box::new(Node::Node(right));
-            Self { bbox, left, right }
+        let mut left = Box::new(left);
+        let mut right = Box::new(right);
+
+        if rand::random() {
+            std::mem::swap(&mut left, &mut right);
+        }
+        Self { bbox, left, right }
+    }
+    /// needs at least 1 object
+    fn new_node(builder: BVHBuilder, scene: Objects) -> Node {
+        if scene.len() == 1 {
+            Node::Object(scene[0])
         } else {
-            let min = Vector3::new(f32::MAX, f32::MAX, f32::MAX);
-            let max = Vector3::new(f32::MIN, f32::MIN, f32::MIN);
-            let mut bbox = BoundingBox { min, max };
-            for (_, sphere) in &scene {
-                bbox = bounding_box::combine(&bbox, &sphere.into());
-            }
-            let diff = max - min;
-
-            if diff.x > diff.y && diff.x > diff.z {
-                scene.sort_by(|a, b| a.1.pos.x.partial_cmp(&b.1.pos.x).unwrap());
-            } else if diff.y > diff.z {
-                scene.sort_by(|a, b| a.1.pos.y.partial_cmp(&b.1.pos.y).unwrap());
-            } else {
-                scene.sort_by(|a, b| a.1.pos.z.partial_cmp(&b.1.pos.z).unwrap());
-            }
+            Node::Node(Self::new(builder, scene))
+        }
+    }
 
-            let scene_other = scene.split_off(scene.len() / 2);
+    fn cost(&self, visit: f32, root_area: f32) -> f32 {
+        // children are only visited when this box is hit
+        let hit = self.bbox.surface_area() / root_area;
+        let child_cost = |node: &Node| match node {
+            Node::Node(interior) => interior.cost(hit, root_area),
+            Node::Object(_) => hit * INTERSECTION_COST,
+        };
 
-            let left = Self::new_interior(scene);
-            let right = Self::new_interior(scene_other);
-            let bbox = bounding_box::combine(&left.bbox, &right.bbox);
+        visit * TRAVERSAL_COST + child_cost(&self.left) + child_cost(&self.right)
+    }
+}
 
-            let mut left = Box::new(Node::Node(left));
-            let mut right = Box::new(Node::Node(right));
+fn centroid_bounds(scene: &Objects) -> BoundingBox {
+    let mut bounds = BoundingBox::EMPTY;
+    for (_, bbox) in scene {
+        let centroid = bbox.centroid();
+        bounds = bounding_box::combine(
+            &bounds,
+            &BoundingBox {
+                min: centroid,
+                max: centroid,
+            },
+        );
+    }
+    bounds
+}
+
+/// Sorts along the longest axis of the centroids and splits in half,
+/// returns the upper half.
+fn split_median(scene: &mut Objects) -> Objects {
+    let bounds = centroid_bounds(scene);
+    let diff = bounds.max - bounds.min;
+
+    let axis = if diff.x > diff.y && diff.x > diff.z {
+        0
+    } else if diff.y > diff.z {
+        1
+    } else {
+        2
+    };
+    scene.sort_by(|a, b| a.1.centroid()[axis].total_cmp(&b.1.centroid()[axis]));
+
+    scene.split_off(scene.len() / 2)
+}
+
+/// Bins the centroids along every axis and splits where the surface area heuristic is lowest,
+/// returns the objects on the upper side. None if there is no split with objects on both sides.
+fn split_sah(scene: &mut Objects) -> Option<Objects> {
+    let bounds = centroid_bounds(scene);
+    let extent = bounds.max - bounds.min;
+    let bin = |bbox: &BoundingBox, axis: usize| {
+        let t = (bbox.centroid()[axis] - bounds.min[axis]) / extent[axis];
+        ((t * SAH_BINS as f32) as usize).min(SAH_BINS - 1)
+    };
 
-            if rand::random() {
-                std::mem::swap(&mut left, &mut right);
+    // cost, axis and last bin of the lower side
+    let mut best: Option<(f32, usize, usize)> = None;
+    for axis in 0..3 {
+        if extent[axis] <= 0.0 {
+            continue;
+        }
+
+        let mut bins = [(0usize, BoundingBox::EMPTY); SAH_BINS];
+        for (_, bbox) in scene.iter() {
+            let b = &mut bins[bin(bbox, axis)];
+            b.0 += 1;
+            b.1 = bounding_box::combine(&b.1, bbox);
+        }
+
+        // count and area of the upper side for a split after each bin
+        let mut upper = [(0usize, 0.0f32); SAH_BINS - 1];
+        let mut count = 0;
+        let mut bbox = BoundingBox::EMPTY;
+        for i in (1..SAH_BINS).rev() {
+            count += bins[i].0;
+            bbox = bounding_box::combine(&bbox, &bins[i].1);
+            upper[i - 1] = (count, bbox.surface_area());
+        }
+
+        let mut count = 0;
+        let mut bbox = BoundingBox::EMPTY;
+        for (i, &(upper_count, upper_area)) in upper.iter().enumerate() {
+            count += bins[i].0;
+            bbox = bounding_box::combine(&bbox, &bins[i].1);
+            if count == 0 || upper_count == 0 {
+                continue;
+            }
+
+            let cost = bbox.surface_area() * count as f32 + upper_area * upper_count as f32;
+            if best.is_none_or(|(best_cost, ..)| cost < best_cost) {
+                best = Some((cost, axis, i));
             }
-            Self { bbox, left, right }
         }
     }
+
+    let (_, axis, split) = best?;
+    let (lower, upper) = scene
+        .drain(..)
+        .partition(|(_, bbox)| bin(bbox, axis) <= split);
+    *scene = lower;
+
+    Some(upper)
 }
 pub fn flatten(bvh: BVHTree) -> Vec<BVHNode> {
     let mut result = vec![];
 
-    flatten_interior(Node::Node(bvh), &mut result);
+    if let Some(root) = bvh.root {
+        flatten_interior(root, &mut result);
+    }
 
     result.into_iter().map(|n| n.unwrap()).collect()
 }
@@ -131,10 +294,77 @@ fn flatten_interior(node: Node, result: &mut Vec<Option<BVHNode>>) {
                 index: result.len() as u32,
             });
         }
-        Node::Object((i, o)) => result.push(Some(BVHNode {
-            bbox: o.into(),
-            index_type: OBJECT,
-            index: i,
-        })),
+        Node::Object((p, bbox)) => {
+            let (index_type, index) = match p {
+                Primitive::Sphere(i) => (SPHERE, i),
+                Primitive::Triangle(i) => (TRIANGLE, i),
+            };
+            result.push(Some(BVHNode {
+                bbox,
+                index_type,
+                index,
+            }))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spheres() -> Vec<Sphere> {
+        // a tight cluster with a few far away outliers
+        let mut spheres: Vec<Sphere> = (0..64)
+            .map(|i| {
+                let pos = Vector3::new((i % 8) as f32 * 0.1, (i / 8) as f32 * 0.1, 0.0);
+                Sphere::new(pos, 0.05, 0, 0)
+            })
+            .collect();
+        for i in 1..=4 {
+            spheres.push(Sphere::new(Vector3::X * 100.0 * i as f32, 1.0, 0, 0));
+        }
+        spheres
+    }
+
+    #[test]
+    fn flatten_every_object() {
+        let spheres = spheres();
+        for builder in [BVHBuilder::Sah, BVHBuilder::Median] {
+            let nodes = flatten(BVHTree::new(builder, &spheres, &[], &[]));
+            assert_eq!(nodes.len(), spheres.len() * 2 - 1);
+
+            let mut leaves: Vec<u32> = nodes
+                .iter()
+                .filter(|n| n.index_type == SPHERE)
+                .map(|n| n.index)
+                .collect();
+            leaves.sort();
+            assert_eq!(leaves, (0..spheres.len() as u32).collect::<Vec<_>>());
+
+            for (i, node) in nodes.iter().enumerate() {
+                if node.index_type == ESCAPE {
+                    assert!(node.index as usize > i && node.index as usize <= nodes.len());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn degenerate_scenes() {
+        for builder in [BVHBuilder::Sah, BVHBuilder::Median] {
+            assert!(flatten(BVHTree::new(builder, &[], &[], &[])).is_empty());
+
+            let nodes = flatten(BVHTree::new(builder, &spheres()[..1], &[], &[]));
+            assert_eq!(nodes.len(), 1);
+            assert_eq!((nodes[0].index_type, nodes[0].index), (SPHERE, 0));
+        }
+    }
+
+    #[test]
+    fn sah_cheaper_than_median() {
+        let spheres = spheres();
+        let sah = BVHTree::new(BVHBuilder::Sah, &spheres, &[], &[]).cost();
+        let median = BVHTree::new(BVHBuilder::Median, &spheres, &[], &[]).cost();
+        assert!(sah < median, "sah: {sah}, median: {median}");
     }
 }
@@ -26,3 +26,8 @@ impl Bytes for i32 {
         Vec::from(bytemuck::bytes_of(self))
     }
 }
+impl Bytes for u32 {
+    fn bytes(&self) -> Vec<u8> {
+        Vec::from(bytemuck::bytes_of(self))
+    }
+}
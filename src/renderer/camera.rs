@@ -9,6 +9,12 @@ pub struct CameraSettings {
     pub pitch: f32,
     /// vertical in degrees
     pub vfov: f32,
+    /// diameter of the lens, zero is a pinhole camera
+    #[serde(default)]
+    pub aperture: f32,
+    /// distance to the plane that is in focus
+    #[serde(default = "default_focus_distance")]
+    pub focus_distance: f32,
 }
 impl Default for CameraSettings {
     fn default() -> Self {
@@ -17,9 +23,14 @@ impl Default for CameraSettings {
             yaw: 0.0,
             pitch: 0.0,
             vfov: 60.0,
+            aperture: 0.0,
+            focus_distance: default_focus_distance(),
         }
     }
 }
+fn default_focus_distance() -> f32 {
+    1.0
+}
 
 #[derive(Clone, Copy, Debug)]
 pub struct CameraConfig {
@@ -31,6 +42,8 @@ pub struct CameraConfig {
     /// vertical fov in radians
     pub vfov: f32,
     pub aspect: f32,
+    pub aperture: f32,
+    pub focus_distance: f32,
 }
 impl CameraConfig {
     pub fn new(settings: CameraSettings, aspect: f32) -> Self {
@@ -40,13 +53,21 @@ impl CameraConfig {
             pitch: settings.pitch.to_radians(),
             vfov: settings.vfov.to_radians(),
             aspect,
+            aperture: settings.aperture,
+            focus_distance: settings.focus_distance,
         }
     }
     pub fn build(&self) -> Camera {
         let dir = self.dir();
         let ray = Ray::new(self.pos, dir);
 
-        Camera::new(&ray, self.vfov, self.aspect)
+        Camera::new(
+            &ray,
+            self.vfov,
+            self.aspect,
+            self.aperture,
+            self.focus_distance,
+        )
     }
     pub fn dir(&self) -> Vector3 {
         let y = self.pitch.sin();
@@ -62,26 +83,29 @@ pub const UP: Vector3 = Vector3::new(0.0, 1.0, 0.0);
 #[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct Camera {
     pos: Vector3,
+    lens_radius: f32,
 
     horizontal: Vector3,
     vertical: Vector3,
     center: Vector3,
 }
 impl Camera {
-    pub fn new(ray: &Ray, vfov: f32, aspect: f32) -> Self {
+    pub fn new(ray: &Ray, vfov: f32, aspect: f32, aperture: f32, focus_distance: f32) -> Self {
         let h = (vfov / 2.0).tan();
         let viewport = h * 2.0;
 
         let h = cross(&UP, &ray.dir).normal();
         let v = cross(&ray.dir, &h);
 
-        let horizontal = h * viewport * aspect;
-        let vertical = v * viewport;
+        // the viewport is placed on the focus plane
+        let horizontal = h * viewport * aspect * focus_distance;
+        let vertical = v * viewport * focus_distance;
 
-        let center = ray.pos + ray.dir;
+        let center = ray.pos + ray.dir * focus_distance;
 
         Self {
             pos: ray.pos,
+            lens_radius: aperture / 2.0,
             horizontal,
             vertical,
             center,
@@ -91,6 +115,7 @@ impl Camera {
 impl Bytes for Camera {
     fn bytes(&self) -> Vec<u8> {
         let b_pos = bytemuck::bytes_of(&self.pos);
+        let b_lens_radius = bytemuck::bytes_of(&self.lens_radius);
         let b_horizontal = bytemuck::bytes_of(&self.horizontal);
         let b_vertical = bytemuck::bytes_of(&self.vertical);
         let b_center = bytemuck::bytes_of(&self.center);
@@ -98,7 +123,7 @@ impl Bytes for Camera {
         let mut v = vec![];
 
         v.extend(b_pos);
-        v.extend(byte);
+        v.extend(b_lens_radius);
 
         v.extend(b_horizontal);
         v.extend(byte);
@@ -1,3 +1,5 @@
+let PI: f32 = 3.14159265;
+
 // --- Random ---
 fn vec2rand(co: vec2<f32>) -> f32{
   return fract(sin(dot(co.xy ,vec2<f32>(12.9898,78.233))) * 43758.5453);
@@ -33,6 +35,19 @@ fn rand_in_sphere(seed: ptr<function, u32>) -> vec3<f32> {
     // should be impossible
     return vec3<f32>(0.0, 0.0, 0.0);
 }
+fn rand_in_disk(seed: ptr<function, u32>) -> vec2<f32> {
+    loop {
+        let p = vec2<f32>(2.0 * randf(seed) - 1.0, 2.0 * randf(seed) - 1.0);
+        if dot(p, p) < 1.0 {
+            return p;
+        }
+    }
+
+    return vec2<f32>(0.0, 0.0);
+}
+fn rand_unit_vector(seed: ptr<function, u32>) -> vec3<f32> {
+    return normalize(rand_in_sphere(seed));
+}
 // --- !Random ---
 
 // --- Ray ---
@@ -56,15 +71,23 @@ fn ray_at(ray: Ray, t: f32) -> vec3<f32> {
 // --- Camera ---
 struct Camera {
     pos: vec3<f32>,
+    lens_radius: f32,
     horizontal: vec3<f32>,
     vertical: vec3<f32>,
+    // center of the viewport on the focus plane
     center: vec3<f32>,
 }
-fn camera_get_ray(camera: Camera, uv: vec2<f32>) -> Ray {
-    return ray_new(
-        camera.pos,
-        normalize(camera.center + camera.horizontal * uv.x + camera.vertical * uv.y - camera.pos)
-    );
+fn camera_get_ray(camera: Camera, uv: vec2<f32>, seed: ptr<function, u32>) -> Ray {
+    let focus = camera.center + camera.horizontal * uv.x + camera.vertical * uv.y;
+
+    // thin lens, rays start on a disk around the camera and meet on the focus plane
+    var pos = camera.pos;
+    if camera.lens_radius > 0.0 {
+        let lens = rand_in_disk(seed) * camera.lens_radius;
+        pos += normalize(camera.horizontal) * lens.x + normalize(camera.vertical) * lens.y;
+    }
+
+    return ray_new(pos, normalize(focus - pos));
 }
 // --- !Camera ---
 // --- Hit Record ---
@@ -73,8 +96,17 @@ struct HitRecord {
     pos: vec3<f32>,
     norm: vec3<f32>,
 
-    sphere_index: u32,
+    mat_type: u32,
+    mat_index: u32,
     back: bool,
+    // texture coordinates, meshes don't have them yet
+    uv: vec2<f32>,
+    // direction u grows in, not yet orthogonal to the normal
+    tangent: vec3<f32>,
+
+    // bvh node type and index of the object that was hit
+    prim_type: u32,
+    prim_index: u32,
 }
 // --- !Hit Record
 // --- Bounding Box ---
@@ -140,6 +172,14 @@ fn ray_sphere_intersect(sphere: Sphere, ray: Ray, t_min: f32, t_max: f32, hit_re
     (*hit_record).t = t;
     (*hit_record).pos = pos;
     (*hit_record).norm = normalize(pos - sphere.pos);
+    // longitude and latitude, v is 0 at the top
+    let n = (*hit_record).norm;
+    (*hit_record).uv = vec2<f32>(atan2(n.x, n.z) / (2.0 * PI) + 0.5, acos(clamp(n.y, -1.0, 1.0)) / PI);
+    // around the y axis, anything at the poles
+    (*hit_record).tangent = vec3<f32>(n.z, 0.0, -n.x);
+    if n.x == 0.0 && n.z == 0.0 {
+        (*hit_record).tangent = vec3<f32>(1.0, 0.0, 0.0);
+    }
     if back {
         (*hit_record).norm = -(*hit_record).norm; 
         // (*hit_record).norm *= -1.0; 
@@ -149,28 +189,261 @@ fn ray_sphere_intersect(sphere: Sphere, ray: Ray, t_min: f32, t_max: f32, hit_re
     return true;
 }
 // --- !Sphere ---
+// --- Triangle ---
+struct Vertex {
+    pos: vec3<f32>,
+    norm: vec3<f32>,
+}
+struct Triangle {
+    indecies: array<u32, 3>,
+    mat_type: u32,
+    mat_index: u32
+}
+// Möller–Trumbore
+fn ray_triangle_intersect(v0: Vertex, v1: Vertex, v2: Vertex, ray: Ray, t_min: f32, t_max: f32, hit_record: ptr<function, HitRecord>) -> bool {
+    let e1 = v1.pos - v0.pos;
+    let e2 = v2.pos - v0.pos;
+
+    let p = cross(ray.dir, e2);
+    let det = dot(e1, p);
+    // ray is parallel to the triangle
+    if abs(det) < 1e-8 {
+        return false;
+    }
+    let inv_det = 1.0 / det;
+
+    let s = ray.pos - v0.pos;
+    let u = dot(s, p) * inv_det;
+    if u < 0.0 || u > 1.0 {
+        return false;
+    }
+
+    let q = cross(s, e1);
+    let v = dot(ray.dir, q) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+        return false;
+    }
+
+    let t = dot(e2, q) * inv_det;
+    if t < t_min || t > t_max {
+        return false;
+    }
+
+    var norm = normalize(cross(e1, e2));
+    // interpolated normal, zero when the mesh is flat shaded
+    let shading = v0.norm * (1.0 - u - v) + v1.norm * u + v2.norm * v;
+    if dot(shading, shading) > 0.0 {
+        // winding decides the geometric normal, so keep it on the same side as the shading normal
+        if dot(norm, shading) < 0.0 {
+            norm = -norm;
+        }
+    }
+    let back = dot(ray.dir, norm) > 0.0;
+    if dot(shading, shading) > 0.0 {
+        norm = normalize(shading);
+    }
+
+    (*hit_record).t = t;
+    (*hit_record).pos = ray_at(ray, t);
+    (*hit_record).norm = norm;
+    (*hit_record).uv = vec2<f32>(0.0);
+    (*hit_record).tangent = e1;
+    if back {
+        (*hit_record).norm = -(*hit_record).norm;
+    }
+    (*hit_record).back = back;
+
+    return true;
+}
+// --- !Triangle ---
 // --- Materials ---
+// normal and bump map textures or NO_TEXTURE
+struct Surface {
+    normal_map: u32,
+    bump_map: u32,
+    bump_strength: f32,
+    emission_texture: u32,
+    // radiance given off by the front, an array so the struct stays 4 byte aligned
+    emission: array<f32, 3>,
+}
 struct Light {
+    // radiance, the intensity is already applied
     colour: vec3<f32>,
+    texture: u32,
 }
+// texture is an index into texture_infos or NO_TEXTURE
 struct Lambertian {
     albedo: vec3<f32>,
+    texture: u32,
+    surface: Surface,
 }
 struct Metal {
     albedo: vec3<f32>,
     roughness: f32,
+    texture: u32,
+    surface: Surface,
 }
 struct Glass {
-    ir: f32
+    ir: f32,
+    surface: Surface,
+}
+struct Conductor {
+    // f0 colour or the real part of the index of refraction
+    eta: vec3<f32>,
+    roughness: f32,
+    // imaginary part of the index of refraction
+    k: vec3<f32>,
+    // 0 colour, 1 complex
+    fresnel_type: u32,
+    surface: Surface,
+}
+struct RoughGlass {
+    ir: f32,
+    roughness: f32,
+    surface: Surface,
+}
+struct Principled {
+    base_colour: vec3<f32>,
+    metallic: f32,
+    roughness: f32,
+    specular: f32,
+    transmission: f32,
+    ir: f32,
+    clearcoat: f32,
+    clearcoat_roughness: f32,
+    sheen: f32,
+    texture: u32,
+    surface: Surface,
+}
+let NO_TEXTURE: u32 = 0xffffffffu;
+struct TextureInfo {
+    colour_a: vec3<f32>,
+    // 0 image, 1 checker, 2 gradient, 3 noise
+    texture_type: u32,
+    colour_b: vec3<f32>,
+    scale: f32,
+    layer: u32,
+    octaves: u32,
 }
 // --- !Materials ---
+// --- Light Sampling ---
+// indecies of the spheres with a light material
+struct LightSpheres {
+    count: u32,
+    indecies: array<u32>,
+}
+struct LightSample {
+    dir: vec3<f32>,
+    // solid angle pdf, zero when the sample is unusable
+    pdf: f32,
+}
+// uniformly samples the cone of directions from pos that hit the sphere
+fn sample_sphere_light(sphere: Sphere, pos: vec3<f32>, seed: ptr<function, u32>) -> LightSample {
+    var light_sample: LightSample;
+    light_sample.pdf = 0.0;
+
+    let to_center = sphere.pos - pos;
+    let dist_sqrd = dot(to_center, to_center);
+    let sin_sqrd = sphere.rad * sphere.rad / dist_sqrd;
+    // inside the light
+    if sin_sqrd >= 1.0 {
+        return light_sample;
+    }
+    let cos_max = sqrt(1.0 - sin_sqrd);
+    // 1 - cos_max without cancellation for small and distant lights
+    let solid = sin_sqrd / (1.0 + cos_max);
+
+    let cos_theta = 1.0 - randf(seed) * solid;
+    let sin_theta = sqrt(max(0.0, 1.0 - cos_theta*cos_theta));
+    let phi = 2.0 * PI * randf(seed);
+
+    // orthonormal basis around the direction to the center
+    let w = to_center / sqrt(dist_sqrd);
+    var a = vec3<f32>(1.0, 0.0, 0.0);
+    if abs(w.x) > 0.9 {
+        a = vec3<f32>(0.0, 1.0, 0.0);
+    }
+    let v = normalize(cross(w, a));
+    let u = cross(v, w);
+
+    light_sample.dir = normalize(u * cos(phi) * sin_theta + v * sin(phi) * sin_theta + w * cos_theta);
+    light_sample.pdf = 1.0 / (2.0 * PI * solid);
+    return light_sample;
+}
+// solid angle pdf of sample_sphere_light() choosing any direction that hits the sphere
+fn sphere_light_pdf(sphere: Sphere, pos: vec3<f32>) -> f32 {
+    let to_center = sphere.pos - pos;
+    let sin_sqrd = sphere.rad * sphere.rad / dot(to_center, to_center);
+    if sin_sqrd >= 1.0 {
+        return 0.0;
+    }
+    let solid = sin_sqrd / (1.0 + sqrt(1.0 - sin_sqrd));
+    return 1.0 / (2.0 * PI * solid);
+}
+// solid angle pdf of a metal scattering towards dir,
+// the scattered ray goes through a point picked uniformly in a ball around the reflection
+fn metal_pdf(reflected: vec3<f32>, dir: vec3<f32>, roughness: f32) -> f32 {
+    // where the line along dir enters and leaves the ball
+    let b = dot(dir, reflected);
+    let d = b*b - 1.0 + roughness*roughness;
+    if d <= 0.0 {
+        return 0.0;
+    }
+    let t0 = max(b - sqrt(d), 0.0);
+    let t1 = b + sqrt(d);
+    if t1 <= 0.0 {
+        return 0.0;
+    }
+    // integral of t^2 dt along the line over the volume of the ball
+    return (t1*t1*t1 - t0*t0*t0) / (4.0 * PI * roughness*roughness*roughness);
+}
+fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+    let a = pdf * pdf;
+    let b = other_pdf * other_pdf;
+    return a / (a + b);
+}
+// --- !Light Sampling ---
+// --- Environment ---
+struct Environment {
+    colour: vec3<f32>,
+    // 0 colour, 1 gradient, 2 image
+    sky_type: u32,
+    rotation: f32,
+    intensity: f32,
+}
+// --- !Environment ---
 // --- Globals ---
 struct Globals {
     seed: u32,
     samples: i32,
     depth: i32,
+    // pixels below this relative variance stop getting samples, 0 turns it off
+    adaptive_threshold: f32,
+    // where the texture is within the whole frame when rendering tiles
+    tile_offset: vec2<u32>,
+    frame_size: vec2<u32>,
+    // 1 writes the aov textures
+    aovs: u32,
+    // no channel of light that bounced more than once goes over this, 0 turns it off
+    indirect_clamp: f32,
+    // no channel of a whole path goes over this, 0 turns it off
+    total_clamp: f32,
 }
 // --- !Globals ---
+// --- AOVs ---
+// what the camera ray hit first
+struct Aov {
+    albedo: vec3<f32>,
+    norm: vec3<f32>,
+    pos: vec3<f32>,
+    // distance along the camera's view direction
+    depth: f32,
+    prim_type: u32,
+    prim_index: u32,
+    mat_type: u32,
+    mat_index: u32,
+}
+// --- !AOVs ---
 
 @group(0) @binding(0)
 var tex: texture_storage_2d<rgba32float,read_write>;
@@ -181,6 +454,37 @@ var<uniform> camera: Camera;
 @group(0) @binding(2)
 var<uniform> globals: Globals;
 
+// sum of every frame's squared colour
+@group(0) @binding(3)
+var moments: texture_storage_2d<rgba32float,read_write>;
+
+// sums of the first hits, 1x1 when aovs are off
+// albedo and the number of frames in alpha
+@group(0) @binding(4)
+var aov_albedo: texture_storage_2d<rgba32float,read_write>;
+@group(0) @binding(5)
+var aov_normal: texture_storage_2d<rgba32float,read_write>;
+// position and depth in alpha
+@group(0) @binding(6)
+var aov_position: texture_storage_2d<rgba32float,read_write>;
+// prim_type, prim_index, mat_type and mat_index of the last frame
+@group(0) @binding(7)
+var aov_id: texture_storage_2d<rgba32float,read_write>;
+
+// copies of the textures before the camera moved, for temporal reprojection
+@group(0) @binding(8)
+var history_tex: texture_2d<f32>;
+@group(0) @binding(9)
+var history_moments: texture_2d<f32>;
+@group(0) @binding(10)
+var history_albedo: texture_2d<f32>;
+@group(0) @binding(11)
+var history_position: texture_2d<f32>;
+@group(0) @binding(12)
+var history_id: texture_2d<f32>;
+@group(0) @binding(13)
+var<uniform> previous_camera: Camera;
+
 @group(1) @binding(0)
 var<storage> bvh: array<BVHNode>;
 @group(1) @binding(1)
@@ -193,6 +497,30 @@ var<storage> lambertians: array<Lambertian>;
 var<storage> metals: array<Metal>;
 @group(1) @binding(5)
 var<storage> glass: array<Glass>;
+@group(1) @binding(6)
+var<storage> vertices: array<Vertex>;
+@group(1) @binding(7)
+var<storage> triangles: array<Triangle>;
+@group(1) @binding(8)
+var<storage> light_spheres: LightSpheres;
+@group(1) @binding(9)
+var<uniform> environment: Environment;
+@group(1) @binding(10)
+var environment_tex: texture_2d<f32>;
+// marginal cdf, conditional cdfs and pdf of the environment image
+@group(1) @binding(11)
+var<storage> environment_distribution: array<f32>;
+@group(1) @binding(12)
+var<storage> conductors: array<Conductor>;
+@group(1) @binding(13)
+var<storage> rough_glass: array<RoughGlass>;
+@group(1) @binding(14)
+var<storage> principled: array<Principled>;
+@group(1) @binding(15)
+var<storage> texture_infos: array<TextureInfo>;
+// every image texture is a layer
+@group(1) @binding(16)
+var texture_images: texture_2d_array<f32>;
 
 fn refract(i: vec3<f32>, n: vec3<f32>, etai_over_etat: f32) -> vec3<f32>{
     let cos_theta = min(dot(-i, n), 1.0);
@@ -205,6 +533,20 @@ fn reflectance(cosine: f32, ref_idx: f32) -> f32 {
     r0 = r0*r0;
     return r0 + (1.0-r0)*pow((1.0 - cosine), 5.0);
 }
+// only glass has an inside, anything else on a triangle is two sided
+fn refracts(mat_type: u32, mat_index: u32) -> bool {
+    switch mat_type {
+        case 3u, 5u: {
+            return true;
+        }
+        case 6u: {
+            return principled[mat_index].transmission > 0.0;
+        }
+        default {
+            return false;
+        }
+    }
+}
 fn closet_hit(ray: Ray, t_min: f32, t_max: f32, hit_record: ptr<function, HitRecord>) -> bool {
     // --- No BVH ---
     // let len = arrayLength(&spheres);
@@ -216,7 +558,8 @@ fn closet_hit(ray: Ray, t_min: f32, t_max: f32, hit_record: ptr<function, HitRec
     //     var hit_record: HitRecord;
     //     if (ray_sphere_intersect(spheres[i], ray, t_min, closet_hit.t, &hit_record)) {
     //         closet_hit = hit_record;
-    //         closet_hit.sphere_index = i;
+    //         closet_hit.mat_type = spheres[i].mat_type;
+    //         closet_hit.mat_index = spheres[i].mat_index;
     //         has_hit = true;
     //     }
     // }
@@ -232,6 +575,8 @@ fn closet_hit(ray: Ray, t_min: f32, t_max: f32, hit_record: ptr<function, HitRec
     closet_hit.t = t_max; // set closet distance to max distance
     var has_hit = false;
 
+    // an empty scene has a single escape node that jumps past the end,
+    // so nothing is hit and only the miss() sky is rendered
     let len = arrayLength(&bvh);
 
     var i = 0u;
@@ -245,12 +590,32 @@ fn closet_hit(ray: Ray, t_min: f32, t_max: f32, hit_record: ptr<function, HitRec
                     i = node.index;
                 }
             }
-            // obj
+            // sphere
             case 1u {
+                let sphere = spheres[node.index];
+                var hit_record: HitRecord;
+                if (ray_sphere_intersect(sphere, ray, t_min, closet_hit.t, &hit_record)) {
+                    closet_hit = hit_record;
+                    closet_hit.mat_type = sphere.mat_type;
+                    closet_hit.mat_index = sphere.mat_index;
+                    closet_hit.prim_type = 1u;
+                    closet_hit.prim_index = node.index;
+                    has_hit = true;
+                }
+            }
+            // triangle
+            case 2u {
+                let tri = triangles[node.index];
+                let v0 = vertices[tri.indecies[0]];
+                let v1 = vertices[tri.indecies[1]];
+                let v2 = vertices[tri.indecies[2]];
                 var hit_record: HitRecord;
-                if (ray_sphere_intersect(spheres[node.index], ray, t_min, closet_hit.t, &hit_record)) {
+                if (ray_triangle_intersect(v0, v1, v2, ray, t_min, closet_hit.t, &hit_record)) {
                     closet_hit = hit_record;
-                    closet_hit.sphere_index = node.index;
+                    closet_hit.mat_type = tri.mat_type;
+                    closet_hit.mat_index = tri.mat_index;
+                    closet_hit.prim_type = 2u;
+                    closet_hit.prim_index = node.index;
                     has_hit = true;
                 }
             }
@@ -261,110 +626,858 @@ fn closet_hit(ray: Ray, t_min: f32, t_max: f32, hit_record: ptr<function, HitRec
     }
     
     if has_hit {
+        // open meshes are seen from behind too, the normal already faces the ray.
+        // lights only shine from their fronts, so watts aren't given off twice
+        if closet_hit.prim_type == 2u && closet_hit.back && closet_hit.mat_type != 0u && !refracts(closet_hit.mat_type, closet_hit.mat_index) {
+            closet_hit.back = false;
+        }
         *hit_record = closet_hit;
         return true;
     }
     return false;
 }
-fn miss(dir_y: f32) -> vec3<f32> {
-    // return vec3<f32>(0.0, 0.0, 0.0); // black/night
-    // return vec3<f32>(1.0, 1.0, 1.0); // white
+// equirectangular lookup, u follows the camera yaw and v goes from straight up to straight down
+fn environment_image(dir: vec3<f32>) -> vec3<f32> {
+    let size = textureDimensions(environment_tex);
+    let phi = atan2(dir.x, dir.z) - environment.rotation;
+    let u = fract(phi / (2.0 * PI) + 0.5);
+    let v = acos(clamp(dir.y, -1.0, 1.0)) / PI;
+
+    // bilinear filtering by hand, the float texture isn't filterable
+    let coords = vec2<f32>(u, v) * vec2<f32>(size) - 0.5;
+    let base = vec2<i32>(floor(coords));
+    let frac = coords - floor(coords);
 
-    // day
-    let t = (dir_y + 1.0) / 2.0;
-    return (1.0 - t) * vec3<f32>(1.0, 1.0, 1.0) + t*vec3<f32>(0.5, 0.7, 1.0);
+    var colour = vec3<f32>(0.0);
+    for (var i = 0; i < 4; i++) {
+        let offset = vec2<i32>(i % 2, i / 2);
+        // wrap around horizontally and clamp at the poles
+        let x = (base.x + offset.x + size.x) % size.x;
+        let y = clamp(base.y + offset.y, 0, size.y - 1);
+        let w = mix(1.0 - frac.x, frac.x, f32(offset.x)) * mix(1.0 - frac.y, frac.y, f32(offset.y));
+        colour += textureLoad(environment_tex, vec2<i32>(x, y), 0).rgb * w;
+    }
+    return colour;
+}
+// last index i in [0, count) of the cdf at offset with cdf[i] <= u
+fn find_interval(offset: u32, count: u32, u: f32) -> u32 {
+    var lo = 0u;
+    var hi = count;
+    while lo + 1u < hi {
+        let mid = (lo + hi) / 2u;
+        if environment_distribution[offset + mid] <= u {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    return lo;
+}
+// samples the environment image proportional to its luminance
+fn sample_environment(seed: ptr<function, u32>) -> LightSample {
+    var light_sample: LightSample;
+    light_sample.pdf = 0.0;
+
+    let size = vec2<u32>(textureDimensions(environment_tex));
+    let w = size.x;
+    let h = size.y;
+
+    // row from the marginal cdf
+    let u1 = randf(seed);
+    let y = find_interval(0u, h, u1);
+    let v0 = environment_distribution[y];
+    let dv = (u1 - v0) / max(environment_distribution[y + 1u] - v0, 1e-8);
+
+    // column from the conditional cdf of that row
+    let row = h + 1u + y * (w + 1u);
+    let u2 = randf(seed);
+    let x = find_interval(row, w, u2);
+    let u0 = environment_distribution[row + x];
+    let du = (u2 - u0) / max(environment_distribution[row + x + 1u] - u0, 1e-8);
+
+    let uv_pdf = environment_distribution[h + 1u + h * (w + 1u) + y * w + x];
+    let u = (f32(x) + clamp(du, 0.0, 1.0)) / f32(w);
+    let v = (f32(y) + clamp(dv, 0.0, 1.0)) / f32(h);
+
+    // inverse of the lookup in environment_image()
+    let theta = v * PI;
+    let phi = (u - 0.5) * 2.0 * PI + environment.rotation;
+    let sin_theta = sin(theta);
+    if sin_theta <= 0.0 || uv_pdf <= 0.0 {
+        return light_sample;
+    }
+
+    light_sample.dir = vec3<f32>(sin_theta * sin(phi), cos(theta), sin_theta * cos(phi));
+    light_sample.pdf = uv_pdf / (2.0 * PI * PI * sin_theta);
+    return light_sample;
+}
+// solid angle pdf of sample_environment() picking dir
+fn environment_pdf(dir: vec3<f32>) -> f32 {
+    let size = vec2<u32>(textureDimensions(environment_tex));
+    let w = size.x;
+    let h = size.y;
+
+    let phi = atan2(dir.x, dir.z) - environment.rotation;
+    let u = fract(phi / (2.0 * PI) + 0.5);
+    let v = acos(clamp(dir.y, -1.0, 1.0)) / PI;
+    let x = min(u32(u * f32(w)), w - 1u);
+    let y = min(u32(v * f32(h)), h - 1u);
+
+    let sin_theta = sqrt(max(0.0, 1.0 - dir.y * dir.y));
+    if sin_theta <= 0.0 {
+        return 0.0;
+    }
+    return environment_distribution[h + 1u + h * (w + 1u) + y * w + x] / (2.0 * PI * PI * sin_theta);
+}
+fn miss(dir: vec3<f32>) -> vec3<f32> {
+    switch environment.sky_type {
+        // colour
+        case 0u {
+            return environment.colour * environment.intensity;
+        }
+        // day gradient
+        case 1u {
+            let t = (dir.y + 1.0) / 2.0;
+            return ((1.0 - t) * vec3<f32>(1.0, 1.0, 1.0) + t*vec3<f32>(0.5, 0.7, 1.0)) * environment.intensity;
+        }
+        // image
+        default {
+            return environment_image(dir) * environment.intensity;
+        }
+    }
+}
+// --- Textures ---
+// bilinear and repeating
+fn texture_image(layer: u32, uv: vec2<f32>) -> vec3<f32> {
+    let size = vec2<i32>(textureDimensions(texture_images));
+    let p = fract(uv) * vec2<f32>(size) - 0.5;
+    let p0 = vec2<i32>(floor(p));
+    let f = p - floor(p);
+
+    let x0 = (p0.x % size.x + size.x) % size.x;
+    let y0 = (p0.y % size.y + size.y) % size.y;
+    let x1 = (x0 + 1) % size.x;
+    let y1 = (y0 + 1) % size.y;
+    let l = i32(layer);
+    let top = mix(textureLoad(texture_images, vec2<i32>(x0, y0), l, 0), textureLoad(texture_images, vec2<i32>(x1, y0), l, 0), f.x);
+    let bottom = mix(textureLoad(texture_images, vec2<i32>(x0, y1), l, 0), textureLoad(texture_images, vec2<i32>(x1, y1), l, 0), f.x);
+    return mix(top, bottom, f.y).rgb;
+}
+// random unit gradient of a lattice point
+fn noise_gradient(p: vec2<i32>) -> vec2<f32> {
+    var h = u32(p.x) * 1597334677u ^ u32(p.y) * 3812015801u;
+    h ^= h >> 16u;
+    h *= 2246822519u;
+    h ^= h >> 13u;
+    let angle = f32(h) / f32(0xffffffffu) * 2.0 * PI;
+    return vec2<f32>(cos(angle), sin(angle));
 }
-fn trace_path(ray: Ray, seed: ptr<function, u32>) -> vec3<f32> {
+// perlin noise in about [-0.7, 0.7], the lattice repeats every period along x
+fn perlin(p: vec2<f32>, period: i32) -> f32 {
+    let i = vec2<i32>(floor(p));
+    let f = p - floor(p);
+    // quintic fade
+    let w = f * f * f * (f * (f * 6.0 - 15.0) + 10.0);
+
+    let x0 = (i.x % period + period) % period;
+    let x1 = (x0 + 1) % period;
+    let a = dot(noise_gradient(vec2<i32>(x0, i.y)), f);
+    let b = dot(noise_gradient(vec2<i32>(x1, i.y)), f - vec2<f32>(1.0, 0.0));
+    let c = dot(noise_gradient(vec2<i32>(x0, i.y + 1)), f - vec2<f32>(0.0, 1.0));
+    let d = dot(noise_gradient(vec2<i32>(x1, i.y + 1)), f - vec2<f32>(1.0, 1.0));
+    return mix(mix(a, b, w.x), mix(c, d, w.x), w.y);
+}
+// octaves of perlin noise in [0, 1], seamless around spheres for whole number scales
+fn fbm(uv: vec2<f32>, scale: f32, octaves: u32) -> f32 {
+    var sum = 0.0;
+    var amplitude = 1.0;
+    var total = 0.0;
+    var frequency = max(round(scale), 1.0);
+    for (var o = 0u; o < octaves; o++) {
+        sum += amplitude * perlin(uv * frequency, i32(frequency));
+        total += amplitude;
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+    return clamp(0.5 + 0.5 * sum / (total * 0.7071), 0.0, 1.0);
+}
+// colour of a texture at uv, white without one
+fn texture_colour(index: u32, uv: vec2<f32>) -> vec3<f32> {
+    if index == NO_TEXTURE {
+        return vec3<f32>(1.0);
+    }
+    let info = texture_infos[index];
+    switch info.texture_type {
+        // image
+        case 0u {
+            return texture_image(info.layer, uv);
+        }
+        // checker
+        case 1u {
+            let cell = floor(uv * info.scale);
+            if fract((cell.x + cell.y) * 0.5) < 0.25 {
+                return info.colour_a;
+            }
+            return info.colour_b;
+        }
+        // noise
+        case 3u {
+            return mix(info.colour_a, info.colour_b, fbm(uv, info.scale, info.octaves));
+        }
+        // gradient
+        default {
+            return mix(info.colour_a, info.colour_b, clamp(uv.y, 0.0, 1.0));
+        }
+    }
+}
+// material colours with their textures applied
+fn light_emission(hit_record: HitRecord) -> vec3<f32> {
+    let material = lights[hit_record.mat_index];
+    return material.colour * texture_colour(material.texture, hit_record.uv);
+}
+fn lambertian_albedo(hit_record: HitRecord) -> vec3<f32> {
+    let material = lambertians[hit_record.mat_index];
+    return material.albedo * texture_colour(material.texture, hit_record.uv);
+}
+fn metal_albedo(hit_record: HitRecord) -> vec3<f32> {
+    let material = metals[hit_record.mat_index];
+    return material.albedo * texture_colour(material.texture, hit_record.uv);
+}
+fn principled_material(hit_record: HitRecord) -> Principled {
+    var material = principled[hit_record.mat_index];
+    material.base_colour *= texture_colour(material.texture, hit_record.uv);
+    return material;
+}
+// --- !Textures ---
+// the bsdf times the cosine towards a direction and the pdf of scattering there
+struct BsdfEval {
+    value: vec3<f32>,
+    pdf: f32,
+}
+// --- Microfacets ---
+// ggx / trowbridge-reitz, directions are in the tangent frame where the normal is z
+let MIN_ALPHA: f32 = 0.001;
+
+// orthonormal basis with n as z, from "Building an Orthonormal Basis, Revisited"
+// world to local is v * frame and local to world is frame * v
+fn tangent_frame(n: vec3<f32>) -> mat3x3<f32> {
+    let s = select(-1.0, 1.0, n.z >= 0.0);
+    let a = -1.0 / (s + n.z);
+    let b = n.x * n.y * a;
+    let t = vec3<f32>(1.0 + s * n.x * n.x * a, s * b, -s * n.x);
+    let bt = vec3<f32>(b, s + n.y * n.y * a, -n.y);
+    return mat3x3<f32>(t, bt, n);
+}
+// perceptual roughness to alpha, a perfect mirror would break the pdfs
+fn ggx_alpha(roughness: f32) -> f32 {
+    return max(roughness * roughness, MIN_ALPHA);
+}
+// normal distribution
+fn ggx_d(h: vec3<f32>, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    let d = h.z * h.z * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+fn ggx_lambda(v: vec3<f32>, alpha: f32) -> f32 {
+    let z2 = v.z * v.z;
+    let tan2 = max(1.0 - z2, 0.0) / max(z2, 1e-8);
+    return (sqrt(1.0 + alpha * alpha * tan2) - 1.0) / 2.0;
+}
+// masking of one direction
+fn ggx_g1(v: vec3<f32>, alpha: f32) -> f32 {
+    return 1.0 / (1.0 + ggx_lambda(v, alpha));
+}
+// height correlated masking and shadowing
+fn ggx_g2(wo: vec3<f32>, wi: vec3<f32>, alpha: f32) -> f32 {
+    return 1.0 / (1.0 + ggx_lambda(wo, alpha) + ggx_lambda(wi, alpha));
+}
+// samples a normal visible from wo, from "Sampling the GGX Distribution of Visible Normals"
+fn sample_ggx_vndf(wo: vec3<f32>, alpha: f32, seed: ptr<function, u32>) -> vec3<f32> {
+    // stretch to the hemisphere configuration
+    let v = normalize(vec3<f32>(alpha * wo.x, alpha * wo.y, max(wo.z, 1e-6)));
+    let len2 = v.x * v.x + v.y * v.y;
+    var t1 = vec3<f32>(1.0, 0.0, 0.0);
+    if len2 > 0.0 {
+        t1 = vec3<f32>(-v.y, v.x, 0.0) / sqrt(len2);
+    }
+    let t2 = cross(v, t1);
+
+    // point on the projected disk
+    let r = sqrt(randf(seed));
+    let phi = 2.0 * PI * randf(seed);
+    let p1 = r * cos(phi);
+    let s = 0.5 * (1.0 + v.z);
+    let p2 = (1.0 - s) * sqrt(1.0 - p1 * p1) + s * r * sin(phi);
+
+    // back onto the hemisphere and unstretch
+    let n = p1 * t1 + p2 * t2 + sqrt(max(1.0 - p1 * p1 - p2 * p2, 0.0)) * v;
+    return normalize(vec3<f32>(alpha * n.x, alpha * n.y, max(n.z, 1e-6)));
+}
+// solid angle pdf of reflecting wo about a normal from sample_ggx_vndf()
+fn ggx_reflection_pdf(wo: vec3<f32>, h: vec3<f32>, alpha: f32) -> f32 {
+    return ggx_g1(wo, alpha) * ggx_d(h, alpha) / (4.0 * wo.z);
+}
+// exact fresnel of a conductor with the complex index of refraction eta + ik
+fn fresnel_complex(cos_i: f32, eta: vec3<f32>, k: vec3<f32>) -> vec3<f32> {
+    let c2 = cos_i * cos_i;
+    let s2 = 1.0 - c2;
+    let eta2 = eta * eta;
+    let k2 = k * k;
+
+    let t0 = eta2 - k2 - s2;
+    let a2b2 = sqrt(t0 * t0 + 4.0 * eta2 * k2);
+    let t1 = a2b2 + c2;
+    let a = sqrt(max(0.5 * (a2b2 + t0), vec3<f32>(0.0)));
+    let t2 = 2.0 * cos_i * a;
+    let rs = (t1 - t2) / (t1 + t2);
+
+    let t3 = c2 * a2b2 + s2 * s2;
+    let t4 = t2 * s2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+    return 0.5 * (rp + rs);
+}
+fn conductor_fresnel(material: Conductor, cos_i: f32) -> vec3<f32> {
+    let cos_i = clamp(cos_i, 0.0, 1.0);
+    if material.fresnel_type == 1u {
+        return fresnel_complex(cos_i, material.eta, material.k);
+    }
+    return material.eta + (1.0 - material.eta) * pow(1.0 - cos_i, 5.0);
+}
+// --- !Microfacets ---
+// --- Principled ---
+fn luminance(colour: vec3<f32>) -> f32 {
+    return dot(colour, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+fn schlick(f0: vec3<f32>, cos_i: f32) -> vec3<f32> {
+    return f0 + (1.0 - f0) * pow(1.0 - clamp(cos_i, 0.0, 1.0), 5.0);
+}
+// specular reflectance head on, dielectrics are at most 8%
+fn principled_f0(material: Principled) -> vec3<f32> {
+    return mix(vec3<f32>(0.08 * material.specular), material.base_colour, material.metallic);
+}
+// chance of sampling the diffuse, specular, clearcoat and transmission lobes from wo
+fn principled_lobes(material: Principled, wo: vec3<f32>) -> vec4<f32> {
+    let dielectric = 1.0 - material.metallic;
+    let lobes = vec4<f32>(
+        dielectric * (1.0 - material.transmission) * (luminance(material.base_colour) + material.sheen),
+        max(luminance(schlick(principled_f0(material), wo.z)), 0.01),
+        0.25 * material.clearcoat * schlick(vec3<f32>(0.04), wo.z).x,
+        dielectric * material.transmission,
+    );
+    return lobes / dot(lobes, vec4<f32>(1.0));
+}
+// reflection of every lobe but the transmission, wo and wi are in the tangent frame
+fn principled_eval(material: Principled, wo: vec3<f32>, wi: vec3<f32>) -> BsdfEval {
+    var eval: BsdfEval;
+    eval.value = vec3<f32>(0.0);
+    eval.pdf = 0.0;
+    if wo.z <= 0.0 || wi.z <= 0.0 {
+        return eval;
+    }
+    let lobes = principled_lobes(material, wo);
+    let h = normalize(wo + wi);
+
+    let sheen = material.sheen * pow(1.0 - dot(wi, h), 5.0);
+    let diffuse = (1.0 - material.metallic) * (1.0 - material.transmission) * (material.base_colour / PI + sheen) * wi.z;
+
+    let alpha = ggx_alpha(material.roughness);
+    let specular = schlick(principled_f0(material), dot(wo, h)) * ggx_d(h, alpha) * ggx_g2(wo, wi, alpha) / (4.0 * wo.z);
+
+    let coat_alpha = ggx_alpha(material.clearcoat_roughness);
+    let coat = 0.25 * material.clearcoat * schlick(vec3<f32>(0.04), dot(wo, h)) * ggx_d(h, coat_alpha) * ggx_g2(wo, wi, coat_alpha) / (4.0 * wo.z);
+
+    eval.value = diffuse + specular + coat;
+    eval.pdf = lobes.x * wi.z / PI + lobes.y * ggx_reflection_pdf(wo, h, alpha) + lobes.z * ggx_reflection_pdf(wo, h, coat_alpha);
+    return eval;
+}
+// --- !Principled ---
+// --- Surface ---
+// uv distance the slope of bump maps is measured over
+let BUMP_STEP: f32 = 0.001;
+
+// tangent, bitangent and normal of the hit, the bitangent is up in images
+// every bsdf samples in this frame, so it's the same for every bounce off a hit
+fn shading_frame(hit_record: HitRecord) -> mat3x3<f32> {
+    let n = hit_record.norm;
+    let t = hit_record.tangent - n * dot(n, hit_record.tangent);
+    if dot(t, t) < 1e-12 {
+        return tangent_frame(n);
+    }
+    let t = normalize(t);
+    return mat3x3<f32>(t, cross(n, t), n);
+}
+fn material_surface(hit_record: HitRecord) -> Surface {
+    switch hit_record.mat_type {
+        case 1u: {
+            return lambertians[hit_record.mat_index].surface;
+        }
+        case 2u: {
+            return metals[hit_record.mat_index].surface;
+        }
+        case 3u: {
+            return glass[hit_record.mat_index].surface;
+        }
+        case 4u: {
+            return conductors[hit_record.mat_index].surface;
+        }
+        case 5u: {
+            return rough_glass[hit_record.mat_index].surface;
+        }
+        case 6u: {
+            return principled[hit_record.mat_index].surface;
+        }
+        default {
+            var surface: Surface;
+            surface.normal_map = NO_TEXTURE;
+            surface.bump_map = NO_TEXTURE;
+            surface.emission_texture = NO_TEXTURE;
+            return surface;
+        }
+    }
+}
+fn surface_emission(hit_record: HitRecord) -> vec3<f32> {
+    let surface = material_surface(hit_record);
+    let emission = vec3<f32>(surface.emission[0], surface.emission[1], surface.emission[2]);
+    return emission * texture_colour(surface.emission_texture, hit_record.uv);
+}
+// tilts the normal by the material's normal and bump maps, wo points back along the ray
+fn apply_surface(hit_record: ptr<function, HitRecord>, wo: vec3<f32>) {
+    let surface = material_surface(*hit_record);
+    if surface.normal_map == NO_TEXTURE && surface.bump_map == NO_TEXTURE {
+        return;
+    }
+    let uv = (*hit_record).uv;
+
+    var n = vec3<f32>(0.0, 0.0, 1.0);
+    if surface.normal_map != NO_TEXTURE {
+        n = normalize(texture_colour(surface.normal_map, uv) * 2.0 - 1.0);
+    }
+    if surface.bump_map != NO_TEXTURE {
+        let height = luminance(texture_colour(surface.bump_map, uv));
+        let du = luminance(texture_colour(surface.bump_map, uv + vec2<f32>(BUMP_STEP, 0.0))) - height;
+        let dv = luminance(texture_colour(surface.bump_map, uv + vec2<f32>(0.0, BUMP_STEP))) - height;
+        // v grows against the bitangent
+        let slope = vec2<f32>(du, -dv) / BUMP_STEP * surface.bump_strength;
+        n = normalize(n - vec3<f32>(slope, 0.0));
+    }
+
+    let norm = normalize(shading_frame(*hit_record) * n);
+    // a normal facing away from the ray would let light through, keep the geometric one then
+    if dot(norm, wo) <= 0.0 {
+        return;
+    }
+    (*hit_record).norm = norm;
+}
+// --- !Surface ---
+// evaluates the materials lights are sampled for, wo points away from the surface
+fn eval_bsdf(hit_record: HitRecord, wo: vec3<f32>, dir: vec3<f32>) -> BsdfEval {
+    var eval: BsdfEval;
+    eval.value = vec3<f32>(0.0);
+    eval.pdf = 0.0;
+    if dot(dir, hit_record.norm) <= 0.0 {
+        return eval;
+    }
+    switch hit_record.mat_type {
+        // lambertian, cosine weighted
+        case 1u: {
+            eval.pdf = dot(dir, hit_record.norm) / PI;
+            eval.value = lambertian_albedo(hit_record) * eval.pdf;
+        }
+        // metal, the pdf is also the bsdf times the cosine
+        case 2u: {
+            let material = metals[hit_record.mat_index];
+            eval.pdf = metal_pdf(reflect(-wo, hit_record.norm), dir, material.roughness);
+            eval.value = metal_albedo(hit_record) * eval.pdf;
+        }
+        // conductor
+        case 4u: {
+            let material = conductors[hit_record.mat_index];
+            let frame = shading_frame(hit_record);
+            let wo_local = wo * frame;
+            let wi_local = dir * frame;
+            if wo_local.z <= 0.0 {
+                return eval;
+            }
+            let alpha = ggx_alpha(material.roughness);
+            let h = normalize(wo_local + wi_local);
+            let d = ggx_d(h, alpha);
+            eval.value = conductor_fresnel(material, dot(wo_local, h)) * d * ggx_g2(wo_local, wi_local, alpha) / (4.0 * wo_local.z);
+            eval.pdf = ggx_reflection_pdf(wo_local, h, alpha);
+        }
+        // principled, lights aren't sampled from inside
+        case 6u: {
+            if hit_record.back {
+                return eval;
+            }
+            let frame = shading_frame(hit_record);
+            return principled_eval(principled_material(hit_record), wo * frame, dir * frame);
+        }
+        default {}
+    }
+    return eval;
+}
+// direct light from one randomly picked light sphere and from the environment image,
+// returns the light times the bsdf and cosine, weighted for mis
+fn sample_light(hit_record: HitRecord, pos: vec3<f32>, wo: vec3<f32>, seed: ptr<function, u32>) -> vec3<f32> {
+    var light = vec3<f32>(0.0);
+
+    let count = light_spheres.count;
+    if count > 0u {
+        let index = light_spheres.indecies[min(u32(randf(seed) * f32(count)), count - 1u)];
+        let sphere = spheres[index];
+
+        let light_sample = sample_sphere_light(sphere, pos, seed);
+        let light_pdf = light_sample.pdf / f32(count);
+        let bsdf = eval_bsdf(hit_record, wo, light_sample.dir);
+        if light_pdf > 0.0 && bsdf.pdf > 0.0 {
+            // shadow ray
+            var shadow_hit: HitRecord;
+            if closet_hit(ray_new(pos, light_sample.dir), 0.0, 1.0 / 0.0, &shadow_hit) {
+                if shadow_hit.prim_type == 1u && shadow_hit.prim_index == index && !shadow_hit.back {
+                    let colour = light_emission(shadow_hit);
+                    light += colour * bsdf.value / light_pdf * power_heuristic(light_pdf, bsdf.pdf);
+                }
+            }
+        }
+    }
+
+    // image
+    if environment.sky_type == 2u {
+        let light_sample = sample_environment(seed);
+        let bsdf = eval_bsdf(hit_record, wo, light_sample.dir);
+        if light_sample.pdf > 0.0 && bsdf.pdf > 0.0 {
+            // shadow ray, the sky is only visible when nothing is hit
+            var shadow_hit: HitRecord;
+            if !closet_hit(ray_new(pos, light_sample.dir), 0.0, 1.0 / 0.0, &shadow_hit) {
+                light += miss(light_sample.dir) * bsdf.value / light_sample.pdf * power_heuristic(light_sample.pdf, bsdf.pdf);
+            }
+        }
+    }
+
+    return light;
+}
+fn first_hit_albedo(hit_record: HitRecord) -> vec3<f32> {
+    switch hit_record.mat_type {
+        case 0u: {
+            return clamp(light_emission(hit_record), vec3<f32>(0.0), vec3<f32>(1.0));
+        }
+        case 1u: {
+            return lambertian_albedo(hit_record);
+        }
+        case 2u: {
+            return metal_albedo(hit_record);
+        }
+        // head on reflectance
+        case 4u: {
+            return clamp(conductor_fresnel(conductors[hit_record.mat_index], 1.0), vec3<f32>(0.0), vec3<f32>(1.0));
+        }
+        case 6u: {
+            return principled_material(hit_record).base_colour;
+        }
+        default {
+            return vec3<f32>(1.0, 1.0, 1.0);
+        }
+    }
+}
+
+// scales the light down until no channel is over the limit, keeps the hue
+fn clamp_radiance(light: vec3<f32>, limit: f32) -> vec3<f32> {
+    let brightest = max(light.x, max(light.y, light.z));
+    if limit > 0.0 && brightest > limit {
+        return light * (limit / brightest);
+    }
+    return light;
+}
+
+// light that bounced off more than one surface before reaching the camera is indirect
+fn clamp_indirect(light: vec3<f32>, bounces: i32) -> vec3<f32> {
+    if bounces > 1 {
+        return clamp_radiance(light, globals.indirect_clamp);
+    }
+    return light;
+}
+
+fn trace_path(ray: Ray, seed: ptr<function, u32>, aov: ptr<function, Aov>) -> vec3<f32> {
     var ray = ray;
 
-    var colour = vec3<f32>(1.0, 1.0, 1.0);
-    var light = vec3<f32>(0.0, 0.0, 0.0);
+    var throughput = vec3<f32>(1.0, 1.0, 1.0);
+    var radiance = vec3<f32>(0.0, 0.0, 0.0);
 
     let t_min = 0.000;
     let t_max = 1.0 / 0.0;
     let dist = 0.001;
 
-    var not_hit_light = true;
+    // pdf of the bsdf that picked the current ray and where it came from,
+    // a pdf of zero means lights weren't sampled there (camera and specular bounces)
+    var bsdf_pdf = 0.0;
+    var last_pos = ray.pos;
+
     var i = 0;
-    while ( i <= globals.depth && not_hit_light) {
+    while i <= globals.depth {
         var hit_record: HitRecord;
-        if closet_hit(ray, t_min, t_max, &hit_record) {
-            let sphere = spheres[hit_record.sphere_index];
-            let new_pos = hit_record.pos + hit_record.norm * dist;
-            switch sphere.mat_type {
-                // light
-                case 0u: {
-                    let material = lights[sphere.mat_index];
-                    light = material.colour;
-                    not_hit_light = false;
+        let hit = closet_hit(ray, t_min, t_max, &hit_record);
+        if hit {
+            apply_surface(&hit_record, -ray.dir);
+        }
+        if i == 0 {
+            if hit {
+                (*aov).albedo = first_hit_albedo(hit_record);
+                (*aov).norm = hit_record.norm;
+                (*aov).pos = hit_record.pos;
+                (*aov).depth = dot(hit_record.pos - camera.pos, normalize(camera.center - camera.pos));
+                (*aov).prim_type = hit_record.prim_type;
+                (*aov).prim_index = hit_record.prim_index;
+                (*aov).mat_type = hit_record.mat_type;
+                (*aov).mat_index = hit_record.mat_index;
+            }
+            else {
+                (*aov).albedo = clamp(miss(ray.dir), vec3<f32>(0.0), vec3<f32>(1.0));
+            }
+        }
+        if !hit {
+            // the image was also sampled directly
+            var weight = 1.0;
+            if bsdf_pdf > 0.0 && environment.sky_type == 2u {
+                weight = power_heuristic(bsdf_pdf, environment_pdf(ray.dir));
+            }
+            radiance += clamp_indirect(throughput * miss(ray.dir) * weight, i);
+            break;
+        }
+        let new_pos = hit_record.pos + hit_record.norm * dist;
+        // towards where the ray came from
+        let wo = -ray.dir;
+        // any material can glow, emissive surfaces aren't sampled directly
+        if hit_record.mat_type != 0u && !hit_record.back {
+            radiance += clamp_indirect(throughput * surface_emission(hit_record), i);
+        }
+        switch hit_record.mat_type {
+            // light
+            case 0u: {
+                // light spheres were also sampled directly
+                var weight = 1.0;
+                if bsdf_pdf > 0.0 && hit_record.prim_type == 1u && light_spheres.count > 0u {
+                    let light_pdf = sphere_light_pdf(spheres[hit_record.prim_index], last_pos) / f32(light_spheres.count);
+                    weight = power_heuristic(bsdf_pdf, light_pdf);
                 }
-                // lambertian
-                case 1u: {
-                    if hit_record.back {
-                        return vec3<f32>(0.0);
-                    }
-                    let material = lambertians[sphere.mat_index];
-                    let scattered = normalize(hit_record.norm + rand_in_sphere(seed));
-                    ray = ray_new(new_pos, scattered);
-                    colour *= material.albedo;
+                // backs of lights are dark
+                if !hit_record.back {
+                    radiance += clamp_indirect(throughput * light_emission(hit_record) * weight, i);
                 }
-                // metal 
-                case 2u: {
-                    if hit_record.back {
-                        return vec3<f32>(0.0);
-                    }
-                    let material = metals[sphere.mat_index];
-                    // let rand_vec = vec3<f32>(
-                        // randf_range(seed, -0.5, 0.5),
-                        // randf_range(seed, -0.5, 0.5),
-                        // randf_range(seed, -0.5, 0.5)
-                    // );
-                    // ray = ray_new(hit_record.pos, (reflect(ray.dir, hit_record.norm + (rand_vec * material.roughness))));
-                    let reflected = normalize(reflect(ray.dir, hit_record.norm) + rand_in_sphere(seed) * material.roughness);
-                    ray = ray_new(new_pos, reflected);
-                    colour *= material.albedo;
+                return radiance;
+            }
+            // lambertian
+            case 1u: {
+                if hit_record.back {
+                    return radiance;
                 }
-                // glass
-                case 3u {
-                    let material = glass[sphere.mat_index];
-                    var ir: f32;
-                    if hit_record.back {
-                        ir = material.ir;
+                radiance += clamp_indirect(throughput * sample_light(hit_record, new_pos, wo, seed), i + 1);
+
+                // cosine weighted, so the albedo is all that's left of the bsdf
+                let scattered = normalize(hit_record.norm + rand_unit_vector(seed));
+                ray = ray_new(new_pos, scattered);
+                throughput *= lambertian_albedo(hit_record);
+                bsdf_pdf = eval_bsdf(hit_record, wo, scattered).pdf;
+            }
+            // metal 
+            case 2u: {
+                if hit_record.back {
+                    return radiance;
+                }
+                let material = metals[hit_record.mat_index];
+                let reflected = reflect(ray.dir, hit_record.norm);
+                // a perfect mirror can't be sampled by lights
+                bsdf_pdf = 0.0;
+                if material.roughness > 0.0 {
+                    radiance += clamp_indirect(throughput * sample_light(hit_record, new_pos, wo, seed), i + 1);
+                }
+
+                let scattered = normalize(reflected + rand_in_sphere(seed) * material.roughness);
+                if material.roughness > 0.0 {
+                    bsdf_pdf = eval_bsdf(hit_record, wo, scattered).pdf;
+                }
+                ray = ray_new(new_pos, scattered);
+                throughput *= metal_albedo(hit_record);
+            }
+            // glass
+            case 3u {
+                let material = glass[hit_record.mat_index];
+                var ir: f32;
+                if hit_record.back {
+                    ir = material.ir;
+                }
+                else {
+                    ir = 1.0 / material.ir;
+                }
+
+                let cos_theta = min(dot(-ray.dir, hit_record.norm), 1.0);
+                let sin_theta = sqrt(1.0 - cos_theta*cos_theta);
+                let cannot_refract = ir * sin_theta > 1.0;
+                var dir: vec3<f32>;
+
+                var new_pos: vec3<f32>;
+                if cannot_refract || reflectance(cos_theta, ir) > randf(seed) {
+                    dir = reflect(ray.dir, hit_record.norm);
+                    new_pos = hit_record.pos + hit_record.norm * dist;
+                }
+                else {
+                    dir = refract(ray.dir, hit_record.norm, ir);
+                    new_pos = hit_record.pos - hit_record.norm * dist;
+                }
+                dir = normalize(dir);
+
+                ray = ray_new(new_pos, dir);
+                bsdf_pdf = 0.0;
+            }
+            // conductor
+            case 4u {
+                if hit_record.back {
+                    return radiance;
+                }
+                let material = conductors[hit_record.mat_index];
+                radiance += clamp_indirect(throughput * sample_light(hit_record, new_pos, wo, seed), i + 1);
+
+                let frame = shading_frame(hit_record);
+                let wo_local = wo * frame;
+                let alpha = ggx_alpha(material.roughness);
+                let h = sample_ggx_vndf(wo_local, alpha, seed);
+                let wi_local = reflect(-wo_local, h);
+                if wi_local.z <= 0.0 {
+                    return radiance;
+                }
+                // the visible normals cancel out everything but the fresnel and some of the masking
+                throughput *= conductor_fresnel(material, dot(wo_local, h)) * ggx_g2(wo_local, wi_local, alpha) / ggx_g1(wo_local, alpha);
+                bsdf_pdf = ggx_reflection_pdf(wo_local, h, alpha);
+                ray = ray_new(new_pos, normalize(frame * wi_local));
+            }
+            // rough glass
+            case 5u {
+                let material = rough_glass[hit_record.mat_index];
+                var ir: f32;
+                if hit_record.back {
+                    ir = material.ir;
+                }
+                else {
+                    ir = 1.0 / material.ir;
+                }
+
+                let frame = shading_frame(hit_record);
+                let wo_local = wo * frame;
+                let alpha = ggx_alpha(material.roughness);
+                let h = sample_ggx_vndf(wo_local, alpha, seed);
+
+                // like glass, but about the microfacet's normal
+                let cos_theta = min(dot(wo_local, h), 1.0);
+                let sin_theta = sqrt(1.0 - cos_theta*cos_theta);
+                let cannot_refract = ir * sin_theta > 1.0;
+                var wi_local: vec3<f32>;
+
+                var new_pos: vec3<f32>;
+                if cannot_refract || reflectance(cos_theta, ir) > randf(seed) {
+                    wi_local = reflect(-wo_local, h);
+                    if wi_local.z <= 0.0 {
+                        return radiance;
                     }
-                    else {
-                        ir = 1.0 / material.ir;
+                    new_pos = hit_record.pos + hit_record.norm * dist;
+                }
+                else {
+                    wi_local = refract(-wo_local, h, ir);
+                    if wi_local.z >= 0.0 {
+                        return radiance;
                     }
+                    new_pos = hit_record.pos - hit_record.norm * dist;
+                }
 
-                    let cos_theta = min(dot(-ray.dir, hit_record.norm), 1.0);
-                    let sin_theta = sqrt(1.0 - cos_theta*cos_theta);
-                    let cannot_refract = ir * sin_theta > 1.0;
-                    var dir: vec3<f32>;
+                throughput *= ggx_g2(wo_local, wi_local, alpha) / ggx_g1(wo_local, alpha);
+                ray = ray_new(new_pos, normalize(frame * wi_local));
+                // lights aren't sampled through glass
+                bsdf_pdf = 0.0;
+            }
+            // principled
+            case 6u {
+                let material = principled_material(hit_record);
+                let frame = shading_frame(hit_record);
+                let wo_local = wo * frame;
+                let alpha = ggx_alpha(material.roughness);
 
+                // from inside only the glass part can be seen, like rough glass
+                if hit_record.back {
+                    if material.transmission <= 0.0 {
+                        return radiance;
+                    }
+                    let h = sample_ggx_vndf(wo_local, alpha, seed);
+                    let cos_theta = min(dot(wo_local, h), 1.0);
+                    let sin_theta = sqrt(1.0 - cos_theta*cos_theta);
+                    var wi_local: vec3<f32>;
                     var new_pos: vec3<f32>;
-                    if cannot_refract || reflectance(cos_theta, ir) > randf(seed) {
-                        dir = reflect(ray.dir, hit_record.norm);
+                    if material.ir * sin_theta > 1.0 || reflectance(cos_theta, material.ir) > randf(seed) {
+                        wi_local = reflect(-wo_local, h);
+                        if wi_local.z <= 0.0 {
+                            return radiance;
+                        }
                         new_pos = hit_record.pos + hit_record.norm * dist;
                     }
                     else {
-                        dir = refract(ray.dir, hit_record.norm, ir);
+                        wi_local = refract(-wo_local, h, material.ir);
+                        if wi_local.z >= 0.0 {
+                            return radiance;
+                        }
                         new_pos = hit_record.pos - hit_record.norm * dist;
                     }
-                    dir = normalize(dir);
+                    throughput *= ggx_g2(wo_local, wi_local, alpha) / ggx_g1(wo_local, alpha);
+                    ray = ray_new(new_pos, normalize(frame * wi_local));
+                    bsdf_pdf = 0.0;
+                    break;
+                }
+
+                radiance += clamp_indirect(throughput * sample_light(hit_record, new_pos, wo, seed), i + 1);
 
-                    ray = ray_new(new_pos, dir);
+                let lobes = principled_lobes(material, wo_local);
+                let u = randf(seed);
+                // transmission
+                if u >= lobes.x + lobes.y + lobes.z {
+                    let h = sample_ggx_vndf(wo_local, alpha, seed);
+                    let ir = 1.0 / material.ir;
+                    let wi_local = refract(-wo_local, h, ir);
+                    if wi_local.z >= 0.0 {
+                        return radiance;
+                    }
+                    // the rest of the fresnel is reflected by the specular lobe
+                    let transmitted = (1.0 - reflectance(dot(wo_local, h), ir)) * ggx_g2(wo_local, wi_local, alpha) / ggx_g1(wo_local, alpha);
+                    throughput *= material.base_colour * (1.0 - material.metallic) * material.transmission * transmitted / lobes.w;
+                    ray = ray_new(hit_record.pos - hit_record.norm * dist, normalize(frame * wi_local));
+                    // lights aren't sampled through glass
+                    bsdf_pdf = 0.0;
+                    break;
                 }
-                default {
-                    return vec3<f32>(0.0, 0.0, 0.0);
+
+                var wi_local: vec3<f32>;
+                if u < lobes.x {
+                    wi_local = normalize(vec3<f32>(0.0, 0.0, 1.0) + rand_unit_vector(seed));
                 }
+                else if u < lobes.x + lobes.y {
+                    wi_local = reflect(-wo_local, sample_ggx_vndf(wo_local, alpha, seed));
+                }
+                else {
+                    let coat_alpha = ggx_alpha(material.clearcoat_roughness);
+                    wi_local = reflect(-wo_local, sample_ggx_vndf(wo_local, coat_alpha, seed));
+                }
+                // every lobe could have picked the direction
+                let eval = principled_eval(material, wo_local, wi_local);
+                if eval.pdf <= 0.0 {
+                    return radiance;
+                }
+                throughput *= eval.value / eval.pdf;
+                bsdf_pdf = eval.pdf;
+                ray = ray_new(new_pos, normalize(frame * wi_local));
+            }
+            default {
+                return vec3<f32>(0.0, 0.0, 0.0);
             }
         }
-        else {
-            light = miss(ray.dir.y);
-            not_hit_light = false;
-        }
+        last_pos = hit_record.pos;
         i += 1;
     }
-    return colour * light;
+    return radiance;
 }
 
 struct In {
@@ -372,6 +1485,47 @@ struct In {
     @builtin(local_invocation_id) i_id: vec3<u32>
 }
 
+// --- Adaptive Sampling ---
+// the noise estimate needs a few frames before it can be trusted
+let ADAPTIVE_MIN_FRAMES: f32 = 16.0;
+// keeps nearly black pixels from never converging
+let DARK_BIAS: f32 = 1e-3;
+// noisy pixels get up to this many times the samples of a frame
+let ADAPTIVE_MAX_SCALE: f32 = 4.0;
+
+// the same estimate as src/convergence.rs, the alpha of sum is the pixel's frame count
+fn relative_variance(sum: vec4<f32>, moment: vec4<f32>) -> f32 {
+    let n = sum.w;
+    let mean = sum.rgb / n;
+    let variance = max(moment.rgb / n - mean * mean, vec3<f32>(0.0)) * n / (n - 1.0);
+    let relative = variance / n / (mean * mean + DARK_BIAS);
+    return max(relative.x, max(relative.y, relative.z));
+}
+// --- !Adaptive Sampling ---
+// --- Temporal Reprojection ---
+// reprojected history counts as at most this many frames, so new samples keep coming through
+let MAX_HISTORY: f32 = 32.0;
+// how far the history's first hit may be from the new one, relative to its distance
+let REPROJECT_TOLERANCE: f32 = 0.02;
+
+// ndc of where the camera sees pos, z is 0 when it's behind the camera
+fn camera_project(camera: Camera, pos: vec3<f32>) -> vec3<f32> {
+    let forward = camera.center - camera.pos;
+    let d = pos - camera.pos;
+    let along = dot(d, forward);
+    if along <= 0.0 {
+        return vec3<f32>(0.0);
+    }
+    // where the line to pos crosses the viewport
+    let q = d * (dot(forward, forward) / along) - forward;
+    return vec3<f32>(
+        dot(q, camera.horizontal) / dot(camera.horizontal, camera.horizontal),
+        dot(q, camera.vertical) / dot(camera.vertical, camera.vertical),
+        1.0
+    );
+}
+// --- !Temporal Reprojection ---
+
 @compute
 @workgroup_size(16,16)
 fn main( in: In ) {
@@ -388,8 +1542,27 @@ fn main( in: In ) {
         return;
     }
 
+    let pc_i32 = vec2<i32>(pixel_coords);
+    var texture_colour = textureLoad(tex, pc_i32);
+    var moment = textureLoad(moments, pc_i32);
+
+    // converged pixels leave the samples to the noisy ones,
+    // which get more of them the further they are from the threshold
+    var samples = globals.samples;
+    if globals.adaptive_threshold > 0.0 && texture_colour.w >= ADAPTIVE_MIN_FRAMES {
+        let noise = relative_variance(texture_colour, moment) / globals.adaptive_threshold;
+        if noise < 1.0 {
+            return;
+        }
+        samples *= i32(min(noise, ADAPTIVE_MAX_SCALE));
+    }
+
+    // the texture might only be a tile of the frame
+    let frame_dimensions = vec2<f32>(globals.frame_size);
+    let frame_coords = pixel_coords + vec2<f32>(globals.tile_offset);
+
     // uv [0.0, 1.0]
-    let uv = (pixel_coords + vec2<f32>(0.5, 0.5)) / texture_dimensions;
+    let uv = (frame_coords + vec2<f32>(0.5, 0.5)) / frame_dimensions;
 
     // normalized device coordinates [-1.0, 1.0]
     var ndc: vec2<f32>;
@@ -401,22 +1574,101 @@ fn main( in: In ) {
 
     // final accumulated colour
     var final_colour = vec3<f32>(0.0, 0.0, 0.0);
+    // averaged first hits, the id is the last sample's
+    var aov_sum: Aov;
 
-    for (var i: i32 = 0; i < globals.samples; i++) {
+    for (var i: i32 = 0; i < samples; i++) {
         // uv and ndc with random offset within the pixel
-        let uv = (pixel_coords + vec2<f32>(randf(&local_seed), randf(&local_seed))) / texture_dimensions;
+        let uv = (frame_coords + vec2<f32>(randf(&local_seed), randf(&local_seed))) / frame_dimensions;
         var ndc: vec2<f32>;
         ndc.x = uv.x * 2.0 - 1.0;
         ndc.y = -(uv.y * 2.0 - 1.0);
 
         // get ray
-        let ray = camera_get_ray(camera, ndc);
+        let ray = camera_get_ray(camera, ndc, &local_seed);
 
-        final_colour += trace_path(ray, &local_seed);
+        var aov: Aov;
+        final_colour += clamp_radiance(trace_path(ray, &local_seed, &aov), globals.total_clamp);
+        aov_sum.albedo += aov.albedo;
+        aov_sum.norm += aov.norm;
+        aov_sum.pos += aov.pos;
+        aov_sum.depth += aov.depth;
+        aov_sum.prim_type = aov.prim_type;
+        aov_sum.prim_index = aov.prim_index;
+        aov_sum.mat_type = aov.mat_type;
+        aov_sum.mat_index = aov.mat_index;
     } 
 
-    let pc_i32 = vec2<i32>(pixel_coords);
-    var texture_colour = textureLoad(tex, pc_i32);
-    texture_colour += vec4<f32>(final_colour / f32(globals.samples), 0.0);
+    let frame_colour = final_colour / f32(samples);
+
+    // alpha counts the frames this pixel got
+    texture_colour += vec4<f32>(frame_colour, 1.0);
     textureStore(tex, pc_i32, texture_colour);
-}
\ No newline at end of file
+
+    // alpha counts the samples, adaptive sampling gives some frames more
+    moment += vec4<f32>(frame_colour * frame_colour, f32(samples));
+    textureStore(moments, pc_i32, moment);
+
+    if globals.aovs != 0u {
+        let n = f32(samples);
+        textureStore(aov_albedo, pc_i32, textureLoad(aov_albedo, pc_i32) + vec4<f32>(aov_sum.albedo / n, 1.0));
+        textureStore(aov_normal, pc_i32, textureLoad(aov_normal, pc_i32) + vec4<f32>(aov_sum.norm / n, 0.0));
+        textureStore(aov_position, pc_i32, textureLoad(aov_position, pc_i32) + vec4<f32>(aov_sum.pos / n, aov_sum.depth / n));
+        textureStore(aov_id, pc_i32, vec4<f32>(f32(aov_sum.prim_type), f32(aov_sum.prim_index), f32(aov_sum.mat_type), f32(aov_sum.mat_index)));
+    }
+}
+
+// Fills the textures from the history after the camera moved,
+// pixels whose first hit wasn't seen before start over.
+@compute
+@workgroup_size(16,16)
+fn reproject( in: In ) {
+    let size = vec2<i32>(textureDimensions(tex));
+    let pixel = vec2<i32>(in.wg_id.xy) * 16 + vec2<i32>(in.i_id.xy);
+    if pixel.x >= size.x || pixel.y >= size.y {
+        return;
+    }
+
+    // the pixel centre's ray, without the lens
+    let frame_dimensions = vec2<f32>(globals.frame_size);
+    let uv = (vec2<f32>(pixel) + vec2<f32>(globals.tile_offset) + vec2<f32>(0.5, 0.5)) / frame_dimensions;
+    let ndc = vec2<f32>(uv.x * 2.0 - 1.0, -(uv.y * 2.0 - 1.0));
+    let focus = camera.center + camera.horizontal * ndc.x + camera.vertical * ndc.y;
+    let ray = ray_new(camera.pos, normalize(focus - camera.pos));
+
+    var hit_record: HitRecord;
+    let hit = closet_hit(ray, 0.0, 1.0 / 0.0, &hit_record);
+    // the sky is reprojected by direction
+    var pos = ray.pos + ray.dir * 1e6;
+    if hit {
+        pos = hit_record.pos;
+    }
+
+    var colour = vec4<f32>(0.0);
+    var moment = vec4<f32>(0.0);
+    let previous = camera_project(previous_camera, pos);
+    if previous.z > 0.0 {
+        let previous_uv = vec2<f32>(previous.x + 1.0, 1.0 - previous.y) / 2.0;
+        let q = vec2<i32>(floor(previous_uv * frame_dimensions)) - vec2<i32>(globals.tile_offset);
+        if q.x >= 0 && q.y >= 0 && q.x < size.x && q.y < size.y {
+            let frames = max(textureLoad(history_albedo, q, 0).w, 1.0);
+            let history_pos = textureLoad(history_position, q, 0).xyz / frames;
+            let history_miss = textureLoad(history_id, q, 0).x == 0.0;
+
+            // disocclusion, something else was seen there before
+            var valid = history_miss;
+            if hit {
+                valid = !history_miss && distance(history_pos, pos) < REPROJECT_TOLERANCE * hit_record.t;
+            }
+            if valid {
+                colour = textureLoad(history_tex, q, 0);
+                moment = textureLoad(history_moments, q, 0);
+                let scale = min(colour.w, MAX_HISTORY) / max(colour.w, 1.0);
+                colour *= scale;
+                moment *= scale;
+            }
+        }
+    }
+    textureStore(tex, pixel, colour);
+    textureStore(moments, pixel, moment);
+}
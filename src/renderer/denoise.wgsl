@@ -0,0 +1,130 @@
+// one iteration of an edge-avoiding à-trous wavelet filter,
+// the input and output are sums with the number of frames in alpha like the accumulation texture
+struct Params {
+    // distance between the taps, doubles every iteration
+    step: i32,
+    colour_phi: f32,
+    normal_phi: f32,
+    depth_phi: f32,
+    albedo_phi: f32,
+}
+
+@group(0) @binding(0)
+var input: texture_2d<f32>;
+@group(0) @binding(1)
+var output: texture_storage_2d<rgba32float,write>;
+@group(0) @binding(2)
+var<uniform> params: Params;
+// raw accumulation and second moments for the noise estimate
+@group(0) @binding(3)
+var accumulation: texture_2d<f32>;
+@group(0) @binding(4)
+var moments: texture_2d<f32>;
+// aov sums, the albedo's alpha is their number of frames
+@group(0) @binding(5)
+var albedo: texture_2d<f32>;
+@group(0) @binding(6)
+var normal: texture_2d<f32>;
+@group(0) @binding(7)
+var position: texture_2d<f32>;
+
+fn luminance(colour: vec3<f32>) -> f32 {
+    return dot(colour, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+struct Guide {
+    colour: vec3<f32>,
+    albedo: vec3<f32>,
+    norm: vec3<f32>,
+    depth: f32,
+}
+fn guide(p: vec2<i32>) -> Guide {
+    let c = textureLoad(input, p, 0);
+    let a = textureLoad(albedo, p, 0);
+    let frames = max(a.w, 1.0);
+
+    var g: Guide;
+    g.colour = c.rgb / max(c.w, 1.0);
+    g.albedo = a.rgb / frames;
+    g.norm = textureLoad(normal, p, 0).xyz / frames;
+    g.depth = textureLoad(position, p, 0).w / frames;
+    return g;
+}
+
+// variance of the pixel's mean luminance, from the moments once there are enough frames
+// and from the neighbours before that
+fn luminance_variance(p: vec2<i32>, size: vec2<i32>) -> f32 {
+    let sum = textureLoad(accumulation, p, 0);
+    let n = sum.w;
+    if n >= 4.0 {
+        let mean = sum.rgb / n;
+        let variance = max(textureLoad(moments, p, 0).rgb / n - mean * mean, vec3<f32>(0.0)) * n / (n - 1.0);
+        return luminance(variance) / n;
+    }
+
+    var l = 0.0;
+    var l2 = 0.0;
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            let q = clamp(p + vec2<i32>(x, y), vec2<i32>(0), size - 1);
+            let c = textureLoad(accumulation, q, 0);
+            let lum = luminance(c.rgb / max(c.w, 1.0));
+            l += lum;
+            l2 += lum * lum;
+        }
+    }
+    l /= 9.0;
+    return max(l2 / 9.0 - l * l, 0.0);
+}
+
+@compute
+@workgroup_size(16,16)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = vec2<i32>(textureDimensions(input));
+    let p = vec2<i32>(id.xy);
+    if p.x >= size.x || p.y >= size.y {
+        return;
+    }
+
+    let frames = textureLoad(input, p, 0).w;
+    let centre = guide(p);
+    let centre_lum = luminance(centre.colour);
+    let sigma = params.colour_phi * sqrt(luminance_variance(p, size)) + 1e-6;
+    // misses have no normal
+    let centre_miss = dot(centre.norm, centre.norm) == 0.0;
+
+    // B3 spline
+    var kernel = array<f32, 5>(1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0);
+
+    var sum = vec3<f32>(0.0);
+    var total = 0.0;
+    for (var y = -2; y <= 2; y++) {
+        for (var x = -2; x <= 2; x++) {
+            let q = p + vec2<i32>(x, y) * params.step;
+            if q.x < 0 || q.y < 0 || q.x >= size.x || q.y >= size.y {
+                continue;
+            }
+            let g = guide(q);
+
+            let miss = dot(g.norm, g.norm) == 0.0;
+            var w_normal = pow(max(dot(centre.norm, g.norm), 0.0), params.normal_phi);
+            if centre_miss || miss {
+                w_normal = select(0.0, 1.0, centre_miss && miss);
+            }
+            let w_depth = exp(-abs(centre.depth - g.depth) / (params.depth_phi * abs(centre.depth) * f32(params.step) + 1e-4));
+            let w_albedo = exp(-length(centre.albedo - g.albedo) / params.albedo_phi);
+            let w_colour = exp(-abs(centre_lum - luminance(g.colour)) / sigma);
+
+            let w = kernel[x + 2] * kernel[y + 2] * w_normal * w_depth * w_albedo * w_colour;
+            sum += g.colour * w;
+            total += w;
+        }
+    }
+
+    // the centre always has a weight, unless it's nan
+    var colour = centre.colour;
+    if total > 0.0 {
+        colour = sum / total;
+    }
+    textureStore(output, p, vec4<f32>(colour * max(frames, 1.0), frames));
+}
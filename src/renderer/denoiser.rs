@@ -0,0 +1,241 @@
+use wgpu::util::DeviceExt;
+
+use super::{bytes::Bytes, copy_texture, texture::Texture, Renderer, SaveInfo};
+
+#[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct DenoiserSettings {
+    /// every iteration doubles the filter's reach
+    pub iterations: u32,
+    /// how many standard deviations of noise still count as the same colour
+    pub colour: f32,
+    /// exponent of the normals' dot product
+    pub normal: f32,
+    /// relative depth difference allowed per pixel of distance
+    pub depth: f32,
+    pub albedo: f32,
+}
+impl DenoiserSettings {
+    /// how far from a pixel the filter reads, the last iteration's kernel and
+    /// the neighbours the variance falls back to
+    pub fn reach(&self) -> u32 {
+        let steps = 1u32.checked_shl(self.iterations.max(1)).unwrap_or(u32::MAX);
+        (steps - 1).saturating_mul(2).saturating_add(1)
+    }
+}
+impl Default for DenoiserSettings {
+    fn default() -> Self {
+        Self {
+            iterations: 5,
+            colour: 4.0,
+            normal: 64.0,
+            depth: 0.02,
+            albedo: 0.1,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct Params {
+    step: i32,
+    colour_phi: f32,
+    normal_phi: f32,
+    depth_phi: f32,
+    albedo_phi: f32,
+}
+impl Bytes for Params {
+    fn bytes(&self) -> Vec<u8> {
+        Vec::from(bytemuck::bytes_of(self))
+    }
+}
+
+const fn texture_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+        },
+        count: None,
+    }
+}
+fn texture(binding: u32, texture: &Texture) -> wgpu::BindGroupEntry<'_> {
+    wgpu::BindGroupEntry {
+        binding,
+        resource: wgpu::BindingResource::TextureView(texture.view()),
+    }
+}
+
+const BIND_GROUP_LAYOUT_DESC: wgpu::BindGroupLayoutDescriptor = wgpu::BindGroupLayoutDescriptor {
+    label: Some("Denoiser Bind Group Layout"),
+    entries: &[
+        // input
+        texture_entry(0),
+        // output
+        wgpu::BindGroupLayoutEntry {
+            binding: 1,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::StorageTexture {
+                access: wgpu::StorageTextureAccess::WriteOnly,
+                format: wgpu::TextureFormat::Rgba32Float,
+                view_dimension: wgpu::TextureViewDimension::D2,
+            },
+            count: None,
+        },
+        // params
+        wgpu::BindGroupLayoutEntry {
+            binding: 2,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        },
+        // accumulation, moments, albedo, normal and position
+        texture_entry(3),
+        texture_entry(4),
+        texture_entry(5),
+        texture_entry(6),
+        texture_entry(7),
+    ],
+};
+
+/// Edge-avoiding à-trous wavelet filter guided by the first hit aovs.
+pub struct Denoiser {
+    pipeline: wgpu::ComputePipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    /// ping pong between these
+    textures: [Texture; 2],
+    /// index of the texture the last iteration wrote to
+    output: usize,
+    settings: DenoiserSettings,
+}
+impl Denoiser {
+    pub fn new(device: &wgpu::Device, settings: DenoiserSettings) -> Self {
+        #[cfg(not(debug_assertions))]
+        let shader = device.create_shader_module(wgpu::include_wgsl!("denoise.wgsl"));
+
+        #[cfg(debug_assertions)]
+        let shader = {
+            let file = std::fs::read_to_string("src/renderer/denoise.wgsl").unwrap();
+            device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("Denoise Shader"),
+                source: wgpu::ShaderSource::Wgsl(std::borrow::Cow::from(file)),
+            })
+        };
+
+        let bind_group_layout = device.create_bind_group_layout(&BIND_GROUP_LAYOUT_DESC);
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Denoiser Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Denoiser Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: "main",
+        });
+
+        // sized by the first denoise
+        let textures = [Texture::new(device, 1, 1), Texture::new(device, 1, 1)];
+
+        Self {
+            pipeline,
+            bind_group_layout,
+            textures,
+            output: 0,
+            settings,
+        }
+    }
+
+    pub fn reload_settings(&mut self, settings: DenoiserSettings) {
+        self.settings = settings;
+    }
+
+    /// Filters the renderer's texture into [`Denoiser::texture`],
+    /// the renderer's aovs have to be turned on.
+    pub fn denoise(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        renderer: &Renderer,
+    ) {
+        let size = renderer.texture().desc().size;
+        for texture in &mut self.textures {
+            if texture.desc().size != size {
+                texture.resize(device, size.width, size.height);
+            }
+        }
+        let aovs = renderer.aovs();
+
+        let mut input = renderer.texture();
+        for i in 0..self.settings.iterations.max(1) {
+            let output = i as usize % 2;
+            let params = Params {
+                step: 1 << i,
+                colour_phi: self.settings.colour,
+                normal_phi: self.settings.normal,
+                depth_phi: self.settings.depth,
+                albedo_phi: self.settings.albedo,
+            };
+            let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Denoiser Params Buffer"),
+                contents: &params.bytes(),
+                usage: wgpu::BufferUsages::UNIFORM,
+            });
+
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Denoiser Bind Group"),
+                layout: &self.bind_group_layout,
+                entries: &[
+                    texture(0, input),
+                    texture(1, &self.textures[output]),
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                            buffer: &params_buffer,
+                            offset: 0,
+                            size: None,
+                        }),
+                    },
+                    texture(3, renderer.texture()),
+                    texture(4, renderer.moments()),
+                    texture(5, &aovs.albedo),
+                    texture(6, &aovs.normal),
+                    texture(7, &aovs.position),
+                ],
+            });
+
+            let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor::default());
+            cpass.set_pipeline(&self.pipeline);
+            cpass.set_bind_group(0, &bind_group, &[]);
+            let width = (size.width as f32 / 16.0).ceil() as u32;
+            let height = (size.height as f32 / 16.0).ceil() as u32;
+            cpass.dispatch_workgroups(width, height, 1);
+            drop(cpass);
+
+            input = &self.textures[output];
+            self.output = output;
+        }
+    }
+
+    /// The last denoised image, laid out like the renderer's texture.
+    pub fn texture(&self) -> &Texture {
+        &self.textures[self.output]
+    }
+
+    /// Copies the last denoised image, see [`Renderer::start_save`].
+    pub fn start_save(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+    ) -> SaveInfo {
+        copy_texture(device, encoder, self.texture())
+    }
+}
@@ -0,0 +1,254 @@
+use wgpu::util::DeviceExt;
+
+use super::{bytes::Bytes, vector3::Vector3};
+
+pub mod indecies {
+    pub const COLOUR: u32 = 0;
+    pub const GRADIENT: u32 = 1;
+    pub const IMAGE: u32 = 2;
+}
+
+/// what rays that don't hit anything see
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub enum Sky {
+    Colour(Vector3),
+    /// white to blue day sky
+    Gradient,
+    /// path to an equirectangular .hdr image
+    Image(String),
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct EnvironmentSettings {
+    pub sky: Sky,
+    /// rotation around the y axis in degrees
+    #[serde(default)]
+    pub rotation: f32,
+    #[serde(default = "default_intensity")]
+    pub intensity: f32,
+}
+impl Default for EnvironmentSettings {
+    fn default() -> Self {
+        Self {
+            sky: Sky::Gradient,
+            rotation: 0.0,
+            intensity: default_intensity(),
+        }
+    }
+}
+fn default_intensity() -> f32 {
+    1.0
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Environment {
+    colour: Vector3,
+    sky_type: u32,
+    /// in radians
+    rotation: f32,
+    intensity: f32,
+}
+impl Bytes for Environment {
+    fn bytes(&self) -> Vec<u8> {
+        let byte = [0u8; 4];
+        let mut v = vec![];
+
+        v.extend(bytemuck::bytes_of(self));
+        v.extend(byte);
+        v.extend(byte);
+
+        v
+    }
+}
+
+/// Environment on the gpu, the texture is a 1x1 dummy unless the sky is an image.
+pub struct EnvironmentBuffers {
+    pub uniform_buffer: wgpu::Buffer,
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+    /// tables for importance sampling the image, see [`distribution`]
+    pub distribution_buffer: wgpu::Buffer,
+}
+impl EnvironmentBuffers {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, settings: &EnvironmentSettings) -> Self {
+        let mut sky = settings.sky.clone();
+
+        let image = match &sky {
+            Sky::Image(path) => match load_image(path) {
+                Ok(image) => Some(image),
+                Err(e) => {
+                    log::error!("{path}: couldn't load environment: {e}");
+                    sky = Sky::Gradient;
+                    None
+                }
+            },
+            _ => None,
+        };
+        let image = image.unwrap_or_else(|| image::Rgba32FImage::new(1, 1));
+
+        let (sky_type, colour) = match sky {
+            Sky::Colour(colour) => (indecies::COLOUR, colour),
+            Sky::Gradient => (indecies::GRADIENT, Vector3::ZERO),
+            Sky::Image(_) => (indecies::IMAGE, Vector3::ZERO),
+        };
+        let environment = Environment {
+            colour,
+            sky_type,
+            rotation: settings.rotation.to_radians(),
+            intensity: settings.intensity,
+        };
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Environment Buffer"),
+            contents: &environment.bytes(),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        let texture = device.create_texture_with_data(
+            queue,
+            &wgpu::TextureDescriptor {
+                label: Some("Environment Texture"),
+                size: wgpu::Extent3d {
+                    width: image.width(),
+                    height: image.height(),
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba32Float,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING,
+            },
+            bytemuck::cast_slice(image.as_raw()),
+        );
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let distribution = if sky_type == indecies::IMAGE {
+            distribution(&image)
+        } else {
+            vec![0.0]
+        };
+        let distribution_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Environment Distribution Buffer"),
+            contents: bytemuck::cast_slice(&distribution),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+
+        Self {
+            uniform_buffer,
+            texture,
+            view,
+            distribution_buffer,
+        }
+    }
+}
+
+/// `image::open` decodes .hdr files to 8 bits, so those go through the hdr decoder
+pub fn load_image(path: &str) -> image::ImageResult<image::Rgba32FImage> {
+    let is_hdr = std::path::Path::new(path)
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("hdr"));
+    if !is_hdr {
+        return Ok(image::open(path)?.into_rgba32f());
+    }
+
+    let file = std::io::BufReader::new(std::fs::File::open(path)?);
+    let decoder = image::codecs::hdr::HdrDecoder::new(file)?;
+    let width = decoder.metadata().width;
+    let height = decoder.metadata().height;
+    let pixels = decoder.read_image_hdr()?;
+
+    Ok(image::Rgba32FImage::from_fn(width, height, |x, y| {
+        let p = pixels[(y * width + x) as usize];
+        image::Rgba([p[0], p[1], p[2], 1.0])
+    }))
+}
+
+/// Piecewise constant 2d distribution over the pixels of an equirectangular image,
+/// proportional to luminance times the solid angle each row covers.
+///
+/// For a width w and height h it's laid out as
+/// - the marginal cdf over rows (h + 1 values)
+/// - the conditional cdf over columns for every row (h * (w + 1) values)
+/// - the pdf over [0, 1]^2 of every pixel (w * h values)
+pub fn distribution(image: &image::Rgba32FImage) -> Vec<f32> {
+    let (w, h) = image.dimensions();
+    let (w, h) = (w as usize, h as usize);
+
+    let mut func: Vec<f32> = image
+        .enumerate_pixels()
+        .map(|(_, y, p)| {
+            let luminance = 0.2126 * p[0] + 0.7152 * p[1] + 0.0722 * p[2];
+            let sin_theta = (std::f32::consts::PI * (y as f32 + 0.5) / h as f32).sin();
+            luminance.max(0.0) * sin_theta
+        })
+        .collect();
+    let total: f32 = func.iter().sum();
+    // a black image is sampled uniformly instead
+    if total <= 0.0 || !total.is_finite() {
+        func.fill(1.0);
+    }
+    let total: f32 = func.iter().sum();
+
+    let mut marginal = Vec::with_capacity(h + 1);
+    let mut conditional = Vec::with_capacity(h * (w + 1));
+    marginal.push(0.0);
+    for row in func.chunks_exact(w) {
+        let row_total: f32 = row.iter().sum();
+        marginal.push(marginal[marginal.len() - 1] + row_total / total);
+
+        let mut sum = 0.0;
+        conditional.push(0.0);
+        for &f in row {
+            sum += f;
+            // an empty row is never picked by the marginal, so it doesn't matter
+            conditional.push(if row_total > 0.0 {
+                sum / row_total
+            } else {
+                1.0
+            });
+        }
+    }
+    // hide rounding errors at the end of the cdf
+    marginal[h] = 1.0;
+
+    let mean = total / (w * h) as f32;
+    let pdf = func.iter().map(|f| f / mean);
+
+    marginal.into_iter().chain(conditional).chain(pdf).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distribution_is_normalized() {
+        let image = image::Rgba32FImage::from_fn(8, 4, |x, y| {
+            let v = (x * y) as f32;
+            image::Rgba([v, v * 0.5, 1.0, 1.0])
+        });
+        let (w, h) = (8, 4);
+        let distribution = distribution(&image);
+        assert_eq!(distribution.len(), (h + 1) + h * (w + 1) + w * h);
+
+        let (marginal, rest) = distribution.split_at(h + 1);
+        let (conditional, pdf) = rest.split_at(h * (w + 1));
+        assert_eq!(marginal[0], 0.0);
+        assert_eq!(marginal[h], 1.0);
+        assert!(marginal.windows(2).all(|c| c[0] <= c[1]));
+        for row in conditional.chunks_exact(w + 1) {
+            assert_eq!(row[0], 0.0);
+            assert!((row[w] - 1.0).abs() < 1e-5);
+        }
+        let integral: f32 = pdf.iter().sum::<f32>() / (w * h) as f32;
+        assert!((integral - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn black_image_is_uniform() {
+        let distribution = distribution(&image::Rgba32FImage::new(4, 2));
+        let pdf = &distribution[distribution.len() - 8..];
+        assert!(pdf.iter().all(|&p| (p - 1.0).abs() < 1e-6));
+    }
+}
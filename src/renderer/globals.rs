@@ -6,18 +6,50 @@ pub struct Globals {
     pub seed: u32,
     pub samples: i32,
     pub depth: i32,
+    /// pixels below this relative variance stop getting samples, 0 turns it off
+    pub adaptive_threshold: f32,
+    /// where the texture is within the whole frame when rendering tiles
+    pub tile_offset: [u32; 2],
+    pub frame_size: [u32; 2],
+    /// 1 writes the aov textures
+    pub aovs: u32,
+    /// no channel of light that bounced more than once goes over this, 0 turns it off
+    pub indirect_clamp: f32,
+    /// no channel of a whole path goes over this, 0 turns it off
+    pub total_clamp: f32,
 }
 impl Globals {
-    pub fn new(seed: u32, samples: i32, depth: i32) -> Self {
+    pub fn new(seed: u32, samples: i32, depth: i32, frame_size: [u32; 2]) -> Self {
         Self {
             seed,
             samples,
             depth,
+            adaptive_threshold: 0.0,
+            tile_offset: [0, 0],
+            frame_size,
+            aovs: 0,
+            indirect_clamp: 0.0,
+            total_clamp: 0.0,
         }
     }
 }
 impl Bytes for Globals {
     fn bytes(&self) -> Vec<u8> {
-        Vec::from(bytemuck::bytes_of(self))
+        let byte = [0u8; 4];
+        let mut v = vec![];
+
+        v.extend(bytemuck::bytes_of(&self.seed));
+        v.extend(bytemuck::bytes_of(&self.samples));
+        v.extend(bytemuck::bytes_of(&self.depth));
+        v.extend(bytemuck::bytes_of(&self.adaptive_threshold));
+
+        v.extend(bytemuck::bytes_of(&self.tile_offset));
+        v.extend(bytemuck::bytes_of(&self.frame_size));
+        v.extend(bytemuck::bytes_of(&self.aovs));
+        v.extend(bytemuck::bytes_of(&self.indirect_clamp));
+        v.extend(bytemuck::bytes_of(&self.total_clamp));
+        v.extend(byte);
+
+        v
     }
 }
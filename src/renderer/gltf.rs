@@ -0,0 +1,252 @@
+use std::{collections::HashMap, path::Path};
+
+use ::gltf::{khr_lights_punctual::Kind, mesh::Mode, Node};
+
+use super::{
+    camera::CameraSettings,
+    materials::*,
+    scene::Scene,
+    sphere::Sphere,
+    triangle::{Triangle, Vertex},
+    vector3::*,
+};
+
+/// column major transform
+type Matrix = [[f32; 4]; 4];
+
+/// glTF is right handed while the renderer is left handed, so z is mirrored
+const HANDEDNESS: Matrix = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, -1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+
+/// punctual lights become small spheres with this radius
+const LIGHT_RADIUS: f32 = 0.05;
+/// luminous efficacy used to turn photometric light units into radiometric ones
+const LUMENS_PER_WATT: f32 = 683.0;
+
+/// Imports the default scene of a .gltf or .glb file.
+/// The first perspective camera found becomes the scene camera.
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Scene, ::gltf::Error> {
+    let (document, buffers, _) = ::gltf::import(path)?;
+
+    let mut importer = Importer {
+        scene: Scene {
+            camera: CameraSettings::default(),
+            spheres: vec![],
+            vertices: vec![],
+            triangles: vec![],
+            environment: Default::default(),
+            lights: vec![],
+            lambertians: vec![],
+            metals: vec![],
+            glass: vec![],
+            conductors: vec![],
+            rough_glass: vec![],
+            principled: vec![],
+            textures: vec![],
+        },
+        buffers,
+        materials: HashMap::new(),
+        has_camera: false,
+    };
+
+    if let Some(scene) = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+    {
+        for node in scene.nodes() {
+            importer.node(&node, &HANDEDNESS);
+        }
+    }
+
+    Ok(importer.scene)
+}
+
+struct Importer {
+    scene: Scene,
+    buffers: Vec<::gltf::buffer::Data>,
+    /// glTF material index to mat_type and mat_index
+    materials: HashMap<Option<usize>, (u32, u32)>,
+    has_camera: bool,
+}
+impl Importer {
+    fn node(&mut self, node: &Node, parent: &Matrix) {
+        let transform = mul(parent, &node.transform().matrix());
+
+        if let Some(mesh) = node.mesh() {
+            for primitive in mesh.primitives() {
+                if primitive.mode() != Mode::Triangles {
+                    log::warn!("skipping mesh primitive with mode {:?}", primitive.mode());
+                    continue;
+                }
+                let material = self.material(&primitive.material());
+                self.primitive(&primitive, &transform, material);
+            }
+        }
+
+        if let Some(camera) = node.camera() {
+            if let ::gltf::camera::Projection::Perspective(perspective) = camera.projection() {
+                if !self.has_camera {
+                    // cameras look down their local -z
+                    let dir = -transform_vector(&transform, &Vector3::Z).normal();
+                    self.scene.camera = CameraSettings {
+                        pos: transform_point(&transform, &Vector3::ZERO),
+                        yaw: dir.x.atan2(dir.z).to_degrees(),
+                        pitch: dir.y.asin().to_degrees(),
+                        vfov: perspective.yfov().to_degrees(),
+                        ..Default::default()
+                    };
+                    self.has_camera = true;
+                }
+            }
+        }
+
+        if let Some(light) = node.light() {
+            match light.kind() {
+                Kind::Point | Kind::Spot { .. } => {
+                    // intensity is in candela, a sphere of radius r with radiance L
+                    // has an intensity of L * pi * r^2
+                    let [r, g, b] = light.color();
+                    let intensity = light.intensity() / LUMENS_PER_WATT;
+                    let radiance = intensity / (std::f32::consts::PI * LIGHT_RADIUS * LIGHT_RADIUS);
+
+                    self.scene.lights.push(Light {
+                        intensity: Intensity::Nits(radiance),
+                        ..Light::new(Vector3::new(r, g, b))
+                    });
+                    self.scene.spheres.push(Sphere::new(
+                        transform_point(&transform, &Vector3::ZERO),
+                        LIGHT_RADIUS,
+                        indecies::LIGHT,
+                        self.scene.lights.len() as u32 - 1,
+                    ));
+                }
+                Kind::Directional => {
+                    log::warn!("directional lights aren't supported");
+                }
+            }
+        }
+
+        for child in node.children() {
+            self.node(&child, &transform);
+        }
+    }
+
+    fn primitive(
+        &mut self,
+        primitive: &::gltf::Primitive,
+        transform: &Matrix,
+        (mat_type, mat_index): (u32, u32),
+    ) {
+        let reader = primitive.reader(|buffer| Some(&self.buffers[buffer.index()]));
+        let Some(positions) = reader.read_positions() else {
+            return;
+        };
+
+        // mirroring transforms flip the winding of the triangles
+        let c = [0, 1, 2].map(|i| Vector3::new(transform[i][0], transform[i][1], transform[i][2]));
+        let det = dot(&c[0], &cross(&c[1], &c[2]));
+
+        let offset = self.scene.vertices.len() as u32;
+        let normals: Vec<[f32; 3]> = reader
+            .read_normals()
+            .map(|normals| normals.collect())
+            .unwrap_or_default();
+        for (i, [x, y, z]) in positions.enumerate() {
+            let pos = transform_point(transform, &Vector3::new(x, y, z));
+
+            // normals are transformed by the inverse transpose,
+            // which is the cofactor matrix divided by the determinant
+            let mut norm = Vector3::ZERO;
+            if let Some(&[x, y, z]) = normals.get(i) {
+                let n = cross(&c[1], &c[2]) * x + cross(&c[2], &c[0]) * y + cross(&c[0], &c[1]) * z;
+                if n.length_sqrd() > 0.0 {
+                    norm = n.normal() * det.signum();
+                }
+            }
+
+            self.scene.vertices.push(Vertex::new(pos, norm));
+        }
+        let count = self.scene.vertices.len() as u32 - offset;
+
+        let indecies: Vec<u32> = match reader.read_indices() {
+            Some(indecies) => indecies.into_u32().collect(),
+            None => (0..count).collect(),
+        };
+        for t in indecies.chunks_exact(3) {
+            let mut t = [t[0] + offset, t[1] + offset, t[2] + offset];
+            if det < 0.0 {
+                t.swap(1, 2);
+            }
+            self.scene
+                .triangles
+                .push(Triangle::new(t, mat_type, mat_index));
+        }
+    }
+
+    /// Maps a pbr metallic roughness material onto a principled material,
+    /// returns its mat_type and mat_index.
+    fn material(&mut self, material: &::gltf::Material) -> (u32, u32) {
+        if let Some(&material) = self.materials.get(&material.index()) {
+            return material;
+        }
+        let scene = &mut self.scene;
+
+        let pbr = material.pbr_metallic_roughness();
+        let [r, g, b, _] = pbr.base_color_factor();
+        let [er, eg, eb] = material.emissive_factor();
+        let principled = Principled {
+            base_colour: Vector3::new(r, g, b),
+            metallic: pbr.metallic_factor(),
+            // gltf's roughness is already ggx's
+            roughness: pbr.roughness_factor(),
+            // a specular factor of 1 is 4%
+            specular: material
+                .specular()
+                .map(|specular| specular.specular_factor() * 0.5)
+                .unwrap_or(0.5),
+            transmission: material
+                .transmission()
+                .map(|transmission| transmission.transmission_factor())
+                .unwrap_or(0.0),
+            ir: material.ior().unwrap_or(Principled::default().ir),
+            surface: Surface {
+                emission: Emission {
+                    colour: Vector3::new(er, eg, eb),
+                    strength: material.emissive_strength().unwrap_or(1.0),
+                    texture: None,
+                },
+                ..Surface::NONE
+            },
+            ..Default::default()
+        };
+
+        scene.principled.push(principled);
+        let mapped = (indecies::PRINCIPLED, scene.principled.len() as u32 - 1);
+        self.materials.insert(material.index(), mapped);
+        mapped
+    }
+}
+
+fn mul(lhs: &Matrix, rhs: &Matrix) -> Matrix {
+    let mut m = [[0.0; 4]; 4];
+    for (c, column) in m.iter_mut().enumerate() {
+        for (r, value) in column.iter_mut().enumerate() {
+            *value = (0..4).map(|k| lhs[k][r] * rhs[c][k]).sum();
+        }
+    }
+    m
+}
+fn transform_point(m: &Matrix, p: &Vector3) -> Vector3 {
+    transform_vector(m, p) + Vector3::new(m[3][0], m[3][1], m[3][2])
+}
+fn transform_vector(m: &Matrix, v: &Vector3) -> Vector3 {
+    Vector3::new(
+        m[0][0] * v.x + m[1][0] * v.y + m[2][0] * v.z,
+        m[0][1] * v.x + m[1][1] * v.y + m[2][1] * v.z,
+        m[0][2] * v.x + m[1][2] * v.y + m[2][2] * v.z,
+    )
+}
@@ -0,0 +1,50 @@
+use super::{aov::Aovs, texture::Texture};
+
+/// Copies of the textures from before the camera moved, for temporal reprojection.
+/// The textures are 1x1 until the first copy.
+pub struct History {
+    pub texture: Texture,
+    pub moments: Texture,
+    pub albedo: Texture,
+    pub position: Texture,
+    pub id: Texture,
+}
+impl History {
+    pub fn new(device: &wgpu::Device) -> Self {
+        Self {
+            texture: Texture::new(device, 1, 1),
+            moments: Texture::new(device, 1, 1),
+            albedo: Texture::new(device, 1, 1),
+            position: Texture::new(device, 1, 1),
+            id: Texture::new(device, 1, 1),
+        }
+    }
+
+    /// Copies the current textures in, the aovs have to be the size of the texture.
+    pub fn copy(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        texture: &Texture,
+        moments: &Texture,
+        aovs: &Aovs,
+    ) {
+        let size = texture.desc().size;
+        for (src, dst) in [
+            (texture, &mut self.texture),
+            (moments, &mut self.moments),
+            (&aovs.albedo, &mut self.albedo),
+            (&aovs.position, &mut self.position),
+            (&aovs.id, &mut self.id),
+        ] {
+            if dst.desc().size != size {
+                dst.resize(device, size.width, size.height);
+            }
+            encoder.copy_texture_to_texture(
+                src.texture().as_image_copy(),
+                dst.texture().as_image_copy(),
+                size,
+            );
+        }
+    }
+}
@@ -1,36 +1,144 @@
-use super::{bytes::Bytes, vector3::Vector3};
+use super::{bytes::Bytes, textures::NO_TEXTURE, vector3::Vector3};
 
 pub mod indecies {
     pub const LIGHT: u32 = 0;
     pub const LAMBERTIAN: u32 = 1;
     pub const METAL: u32 = 2;
     pub const GLASS: u32 = 3;
+    pub const CONDUCTOR: u32 = 4;
+    pub const ROUGH_GLASS: u32 = 5;
+    pub const PRINCIPLED: u32 = 6;
+}
+
+/// Light given off by the front of any material, on top of what it reflects.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct Emission {
+    pub colour: Vector3,
+    /// radiance in nits, the colour is multiplied by it
+    pub strength: f32,
+    /// index into the scene's textures, multiplies the colour
+    pub texture: Option<u32>,
+}
+impl Emission {
+    pub const NONE: Self = Self {
+        colour: Vector3::ONE,
+        strength: 0.0,
+        texture: None,
+    };
+}
+impl Default for Emission {
+    fn default() -> Self {
+        Self::NONE
+    }
+}
+
+/// Normal and bump maps that tilt the shading normal, indices into the scene's textures,
+/// and the light the surface gives off.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct Surface {
+    /// tangent space normals in rgb, green is up in the image
+    pub normal_map: Option<u32>,
+    /// brightness is the height
+    pub bump_map: Option<u32>,
+    /// scales the slope of the height along the uv
+    pub bump_strength: f32,
+    pub emission: Emission,
+}
+impl Surface {
+    pub const NONE: Self = Self {
+        normal_map: None,
+        bump_map: None,
+        bump_strength: 0.01,
+        emission: Emission::NONE,
+    };
+}
+impl Default for Surface {
+    fn default() -> Self {
+        Self::NONE
+    }
+}
+impl Bytes for Surface {
+    fn bytes(&self) -> Vec<u8> {
+        let mut v = vec![];
+
+        v.extend(bytemuck::bytes_of(&self.normal_map.unwrap_or(NO_TEXTURE)));
+        v.extend(bytemuck::bytes_of(&self.bump_map.unwrap_or(NO_TEXTURE)));
+        v.extend(bytemuck::bytes_of(&self.bump_strength));
+        v.extend(bytemuck::bytes_of(
+            &self.emission.texture.unwrap_or(NO_TEXTURE),
+        ));
+        v.extend(bytemuck::bytes_of(
+            &(self.emission.colour * self.emission.strength),
+        ));
+
+        v
+    }
+}
+
+/// How bright a light is, apart from its colour.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum Intensity {
+    /// radiance, the colour is multiplied by it as is
+    Nits(f32),
+    /// power shared by every sphere and triangle with the light, given off by their fronts
+    Watts(f32),
+}
+impl Default for Intensity {
+    fn default() -> Self {
+        Self::Nits(1.0)
+    }
 }
 
 #[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize)]
 pub struct Light {
     pub colour: Vector3,
+    #[serde(default)]
+    pub intensity: Intensity,
+    /// index into the scene's textures, multiplies the colour
+    #[serde(default)]
+    pub texture: Option<u32>,
 }
 impl Light {
     pub const fn new(colour: Vector3) -> Self {
-        Self { colour }
+        Self {
+            colour,
+            intensity: Intensity::Nits(1.0),
+            texture: None,
+        }
+    }
+    /// radiance of the light when it covers a surface of this area
+    pub fn radiance(&self, area: f32) -> f32 {
+        match self.intensity {
+            Intensity::Nits(nits) => nits,
+            // a diffuse emitter gives off pi times its radiance per area
+            Intensity::Watts(watts) if area > 0.0 => watts / (std::f32::consts::PI * area),
+            Intensity::Watts(_) => 0.0,
+        }
+    }
+    /// the same light with its intensity in nits
+    pub fn in_nits(&self, area: f32) -> Self {
+        Self {
+            intensity: Intensity::Nits(self.radiance(area)),
+            ..*self
+        }
     }
 }
 impl Default for Light {
     fn default() -> Self {
-        Self {
-            colour: Vector3::ONE,
-        }
+        Self::new(Vector3::ONE)
     }
 }
+/// lights in watts have to be converted with `in_nits` first, they're black otherwise
 impl Bytes for Light {
     fn bytes(&self) -> Vec<u8> {
-        let b_colour = bytemuck::bytes_of(&self.colour);
-        let byte = [0u8; 4];
+        let colour = self.colour * self.radiance(0.0);
+        let texture = self.texture.unwrap_or(NO_TEXTURE);
         let mut v = vec![];
 
-        v.extend(b_colour);
-        v.extend(byte);
+        v.extend(bytemuck::bytes_of(&colour));
+        v.extend(bytemuck::bytes_of(&texture));
 
         v
     }
@@ -38,27 +146,38 @@ impl Bytes for Light {
 #[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize)]
 pub struct Lambertian {
     pub albedo: Vector3,
+    /// index into the scene's textures, multiplies the albedo
+    #[serde(default)]
+    pub texture: Option<u32>,
+    #[serde(default)]
+    pub surface: Surface,
 }
 
 impl Lambertian {
     pub const fn new(albedo: Vector3) -> Self {
-        Self { albedo }
+        Self {
+            albedo,
+            texture: None,
+            surface: Surface::NONE,
+        }
     }
 }
 impl Default for Lambertian {
     fn default() -> Self {
-        Self {
-            albedo: Vector3::new(0.5, 0.5, 0.5),
-        }
+        Self::new(Vector3::new(0.5, 0.5, 0.5))
     }
 }
 impl Bytes for Lambertian {
     fn bytes(&self) -> Vec<u8> {
         let b_albedo = bytemuck::bytes_of(&self.albedo);
+        let texture = self.texture.unwrap_or(NO_TEXTURE);
         let byte = [0u8; 4];
         let mut v = vec![];
 
         v.extend(b_albedo);
+        v.extend(bytemuck::bytes_of(&texture));
+
+        v.extend(self.surface.bytes());
         v.extend(byte);
 
         v
@@ -68,50 +187,289 @@ impl Bytes for Lambertian {
 pub struct Metal {
     pub albedo: Vector3,
     pub roughness: f32,
+    /// index into the scene's textures, multiplies the albedo
+    #[serde(default)]
+    pub texture: Option<u32>,
+    #[serde(default)]
+    pub surface: Surface,
 }
 
 impl Metal {
     pub const fn new(albedo: Vector3, roughness: f32) -> Self {
-        Self { albedo, roughness }
+        Self {
+            albedo,
+            roughness,
+            texture: None,
+            surface: Surface::NONE,
+        }
     }
 }
 impl Default for Metal {
     fn default() -> Self {
-        Self {
-            albedo: Vector3::new(0.5, 0.5, 0.5),
-            roughness: 0.5,
-        }
+        Self::new(Vector3::new(0.5, 0.5, 0.5), 0.5)
     }
 }
 impl Bytes for Metal {
     fn bytes(&self) -> Vec<u8> {
         let b_albedo = bytemuck::bytes_of(&self.albedo);
         let b_roughness = bytemuck::bytes_of(&self.roughness);
+        let texture = self.texture.unwrap_or(NO_TEXTURE);
         let mut v = vec![];
 
         v.extend(b_albedo);
         v.extend(b_roughness);
 
+        v.extend(bytemuck::bytes_of(&texture));
+        v.extend(self.surface.bytes());
+
         v
     }
 }
 #[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize)]
 pub struct Glass {
     pub ir: f32,
+    #[serde(default)]
+    pub surface: Surface,
 }
 
 impl Glass {
     pub const fn new(ir: f32) -> Self {
-        Self { ir }
+        Self {
+            ir,
+            surface: Surface::NONE,
+        }
     }
 }
 impl Default for Glass {
     fn default() -> Self {
-        Self { ir: 1.5 }
+        Self::new(1.5)
     }
 }
 impl Bytes for Glass {
     fn bytes(&self) -> Vec<u8> {
-        Vec::from(bytemuck::bytes_of(&self.ir))
+        let mut v = Vec::from(bytemuck::bytes_of(&self.ir));
+        v.extend(self.surface.bytes());
+        v
+    }
+}
+
+/// How much light a conductor reflects at every angle.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum Fresnel {
+    /// reflectance head on, Schlick's approximation brightens it towards grazing angles
+    Colour(Vector3),
+    /// complex index of refraction per rgb channel, like gold's
+    /// `Complex(eta: (x: 0.18, y: 0.42, z: 1.37), k: (x: 3.42, y: 2.35, z: 1.77))`
+    Complex { eta: Vector3, k: Vector3 },
+}
+
+/// GGX microfacet metal.
+#[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Conductor {
+    pub fresnel: Fresnel,
+    /// perceptual roughness in [0, 1], squared for the GGX alpha like gltf's
+    pub roughness: f32,
+    #[serde(default)]
+    pub surface: Surface,
+}
+
+impl Conductor {
+    pub const fn new(fresnel: Fresnel, roughness: f32) -> Self {
+        Self {
+            fresnel,
+            roughness,
+            surface: Surface::NONE,
+        }
+    }
+}
+impl Default for Conductor {
+    fn default() -> Self {
+        Self::new(Fresnel::Colour(Vector3::new(0.9, 0.9, 0.9)), 0.3)
+    }
+}
+impl Bytes for Conductor {
+    fn bytes(&self) -> Vec<u8> {
+        let (fresnel_type, eta, k) = match self.fresnel {
+            Fresnel::Colour(colour) => (0u32, colour, Vector3::ZERO),
+            Fresnel::Complex { eta, k } => (1u32, eta, k),
+        };
+        let mut v = vec![];
+
+        v.extend(bytemuck::bytes_of(&eta));
+        v.extend(bytemuck::bytes_of(&self.roughness));
+        v.extend(bytemuck::bytes_of(&k));
+        v.extend(bytemuck::bytes_of(&fresnel_type));
+
+        v.extend(self.surface.bytes());
+        v.extend([0u8; 4]);
+
+        v
+    }
+}
+/// GGX microfacet glass, frosted when it's rough.
+#[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize)]
+pub struct RoughGlass {
+    pub ir: f32,
+    /// perceptual roughness in [0, 1], squared for the GGX alpha like gltf's
+    pub roughness: f32,
+    #[serde(default)]
+    pub surface: Surface,
+}
+
+impl RoughGlass {
+    pub const fn new(ir: f32, roughness: f32) -> Self {
+        Self {
+            ir,
+            roughness,
+            surface: Surface::NONE,
+        }
+    }
+}
+impl Default for RoughGlass {
+    fn default() -> Self {
+        Self::new(1.5, 0.2)
+    }
+}
+impl Bytes for RoughGlass {
+    fn bytes(&self) -> Vec<u8> {
+        let mut v = vec![];
+
+        v.extend(bytemuck::bytes_of(&self.ir));
+        v.extend(bytemuck::bytes_of(&self.roughness));
+        v.extend(self.surface.bytes());
+
+        v
+    }
+}
+/// Disney style material that blends diffuse, metal, glass, clearcoat and sheen.
+/// Any field left out of a scene file gets its default.
+#[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct Principled {
+    pub base_colour: Vector3,
+    /// 0 is a dielectric and 1 a conductor tinted by the base colour
+    pub metallic: f32,
+    /// perceptual roughness of the specular and transmission
+    pub roughness: f32,
+    /// dielectric reflectance head on, 0.5 is 4%
+    pub specular: f32,
+    /// how much of the dielectric part refracts instead of being diffuse
+    pub transmission: f32,
+    pub ir: f32,
+    /// strength of a white ggx layer on top
+    pub clearcoat: f32,
+    pub clearcoat_roughness: f32,
+    /// velvety reflection at grazing angles
+    pub sheen: f32,
+    /// index into the scene's textures, multiplies the base colour
+    pub texture: Option<u32>,
+    pub surface: Surface,
+}
+
+impl Principled {
+    pub fn new(base_colour: Vector3, metallic: f32, roughness: f32) -> Self {
+        Self {
+            base_colour,
+            metallic,
+            roughness,
+            ..Default::default()
+        }
+    }
+}
+impl Default for Principled {
+    fn default() -> Self {
+        Self {
+            base_colour: Vector3::new(0.8, 0.8, 0.8),
+            metallic: 0.0,
+            roughness: 0.5,
+            specular: 0.5,
+            transmission: 0.0,
+            ir: 1.5,
+            clearcoat: 0.0,
+            clearcoat_roughness: 0.03,
+            sheen: 0.0,
+            texture: None,
+            surface: Surface::NONE,
+        }
+    }
+}
+impl Bytes for Principled {
+    fn bytes(&self) -> Vec<u8> {
+        let byte = [0u8; 4];
+        let mut v = vec![];
+
+        v.extend(bytemuck::bytes_of(&self.base_colour));
+        v.extend(bytemuck::bytes_of(&self.metallic));
+
+        v.extend(bytemuck::bytes_of(&self.roughness));
+        v.extend(bytemuck::bytes_of(&self.specular));
+        v.extend(bytemuck::bytes_of(&self.transmission));
+        v.extend(bytemuck::bytes_of(&self.ir));
+
+        v.extend(bytemuck::bytes_of(&self.clearcoat));
+        v.extend(bytemuck::bytes_of(&self.clearcoat_roughness));
+        v.extend(bytemuck::bytes_of(&self.sheen));
+        v.extend(bytemuck::bytes_of(&self.texture.unwrap_or(NO_TEXTURE)));
+
+        v.extend(self.surface.bytes());
+        v.extend(byte);
+
+        v
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conductor_matches_the_shader_layout() {
+        let conductor: Conductor = ron::from_str(
+            "(fresnel: Complex(eta: (x: 0.18, y: 0.42, z: 1.37), k: (x: 3.42, y: 2.35, z: 1.77)), roughness: 0.3, surface: (normal_map: Some(2)))",
+        )
+        .unwrap();
+        let bytes = conductor.bytes();
+        assert_eq!(bytes.len(), 64);
+        let words: &[f32] = bytemuck::cast_slice(&bytes);
+        assert_eq!(&words[..4], &[0.18, 0.42, 1.37, 0.3]);
+        assert_eq!(&words[4..7], &[3.42, 2.35, 1.77]);
+        assert_eq!(bytemuck::cast_slice::<u8, u32>(&bytes)[7], 1);
+        assert_eq!(
+            &bytemuck::cast_slice::<u8, u32>(&bytes)[8..10],
+            &[2, u32::MAX]
+        );
+
+        let colour = Conductor::default().bytes();
+        assert_eq!(bytemuck::cast_slice::<u8, u32>(&colour)[7], 0);
+        assert_eq!(RoughGlass::default().bytes().len(), 36);
+    }
+
+    #[test]
+    fn principled_fields_default() {
+        let principled: Principled = ron::from_str("(metallic: 1.0)").unwrap();
+        assert_eq!(principled.metallic, 1.0);
+        assert_eq!(principled.ir, Principled::default().ir);
+        assert_eq!(principled.bytes().len(), 80);
+    }
+
+    #[test]
+    fn watts_spread_over_the_area() {
+        let light: Light = ron::from_str(
+            "(colour: (x: 1.0, y: 0.5, z: 0.0), intensity: Watts(100.0), texture: Some(3))",
+        )
+        .unwrap();
+        let radiance = 100.0 / (std::f32::consts::PI * 4.0);
+        assert!((light.radiance(4.0) - radiance).abs() < 1e-5);
+        assert_eq!(light.radiance(0.0), 0.0);
+
+        let bytes = light.in_nits(4.0).bytes();
+        assert_eq!(bytes.len(), 16);
+        let words: &[f32] = bytemuck::cast_slice(&bytes);
+        assert!((words[0] - radiance).abs() < 1e-5);
+        assert!((words[1] - radiance * 0.5).abs() < 1e-5);
+        assert_eq!(bytemuck::cast_slice::<u8, u32>(&bytes)[3], 3);
+
+        let nits: Light = ron::from_str("(colour: (x: 1.0, y: 1.0, z: 1.0))").unwrap();
+        assert_eq!(nits.intensity, Intensity::Nits(1.0));
     }
 }
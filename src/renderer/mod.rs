@@ -1,14 +1,24 @@
+pub mod aov;
 pub mod bounding_box;
 pub mod bvh;
 pub mod bytes;
 pub mod camera;
 pub mod compute_pipeline;
+pub mod denoiser;
+pub mod environment;
 pub mod globals;
+pub mod gltf;
+pub mod history;
 pub mod materials;
+pub mod obj;
+pub mod outliers;
 pub mod ray;
 pub mod scene;
 pub mod sphere;
 pub mod texture;
+pub mod textures;
+pub mod tonemap;
+pub mod triangle;
 pub mod vector3;
 
 use camera::CameraConfig;
@@ -17,94 +27,205 @@ use globals::Globals;
 use wgpu::util::DeviceExt;
 
 use self::{
-    bvh::{flatten, BVHTree},
+    aov::Aovs,
+    bvh::{flatten, BVHBuilder, BVHTree},
     bytes::Bytes,
+    denoiser::DenoiserSettings,
+    environment::EnvironmentBuffers,
+    history::History,
+    materials::indecies,
     scene::Scene,
     texture::Texture,
+    textures::TextureBuffers,
+    tonemap::ToneMapping,
 };
 
 #[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize)]
 pub struct Settings {
     pub samples: i32,
     pub depth: i32,
+    /// used the next time a scene is loaded
+    #[serde(default)]
+    pub bvh: BVHBuilder,
+    #[serde(default)]
+    pub tone_mapping: ToneMapping,
+    /// pixels whose estimated relative variance is below this stop getting samples,
+    /// 0 samples every pixel every frame
+    #[serde(default)]
+    pub adaptive_threshold: f32,
+    /// also writes the albedo, normal, position, depth and ids of the first hits
+    #[serde(default)]
+    pub aovs: bool,
+    #[serde(default)]
+    pub denoiser: DenoiserSettings,
+    /// moving the camera reprojects the accumulated frames instead of starting over,
+    /// turns on the aovs
+    #[serde(default)]
+    pub temporal: bool,
+    /// no channel of light that bounced more than once goes over this, 0 turns it off
+    #[serde(default)]
+    pub indirect_clamp: f32,
+    /// no channel of a whole path goes over this, 0 turns it off
+    #[serde(default)]
+    pub total_clamp: f32,
+    /// saved pixels this many standard deviations brighter than their neighbours
+    /// are replaced by the neighbours' mean, 0 turns it off
+    #[serde(default)]
+    pub outlier_rejection: f32,
 }
 
 struct SceneBuffers {
     // spheres
     spheres_buffer: wgpu::Buffer,
 
+    // triangles
+    vertices_buffer: wgpu::Buffer,
+    triangles_buffer: wgpu::Buffer,
+
     // materials
     lights_buffer: wgpu::Buffer,
     lambertians_buffer: wgpu::Buffer,
     metals_buffer: wgpu::Buffer,
     glass_buffer: wgpu::Buffer,
+    conductors_buffer: wgpu::Buffer,
+    rough_glass_buffer: wgpu::Buffer,
+    principled_buffer: wgpu::Buffer,
+    textures: TextureBuffers,
     bvh_buffer: wgpu::Buffer,
+
+    // spheres sampled directly for next event estimation
+    light_spheres_buffer: wgpu::Buffer,
+
+    environment: EnvironmentBuffers,
 }
 impl SceneBuffers {
-    fn new(device: &wgpu::Device, scene: Scene) -> Self {
+    fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        scene: Scene,
+        bvh_builder: BVHBuilder,
+    ) -> Self {
         // get spheres onto the gpu
-        let spheres_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Spheres Buffer"),
-            contents: &scene.spheres.bytes(),
-            usage: wgpu::BufferUsages::STORAGE,
-        });
+        let spheres_buffer = storage_buffer(device, "Spheres Buffer", &scene.spheres);
+        // get triangles onto the gpu
+        let vertices_buffer = storage_buffer(device, "Vertices Buffer", &scene.vertices);
+        let triangles_buffer = storage_buffer(device, "Triangles Buffer", &scene.triangles);
         // get materials onto the gpu
-        let lights_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Lights Buffer"),
-            contents: &scene.lights.bytes(),
-            usage: wgpu::BufferUsages::STORAGE,
-        });
-        let lambertians_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Lambertians Buffer"),
-            contents: &scene.lambertians.bytes(),
-            usage: wgpu::BufferUsages::STORAGE,
-        });
-        let metals_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Metals Buffer"),
-            contents: &scene.metals.bytes(),
-            usage: wgpu::BufferUsages::STORAGE,
-        });
-        let glass_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Glass Buffer"),
-            contents: &scene.glass.bytes(),
+        let lights_buffer = storage_buffer(device, "Lights Buffer", &scene.lights_in_nits());
+        let lambertians_buffer = storage_buffer(device, "Lambertians Buffer", &scene.lambertians);
+        let metals_buffer = storage_buffer(device, "Metals Buffer", &scene.metals);
+        let glass_buffer = storage_buffer(device, "Glass Buffer", &scene.glass);
+        let conductors_buffer = storage_buffer(device, "Conductors Buffer", &scene.conductors);
+        let rough_glass_buffer = storage_buffer(device, "Rough Glass Buffer", &scene.rough_glass);
+        let principled_buffer = storage_buffer(device, "Principled Buffer", &scene.principled);
+        let textures = TextureBuffers::new(device, queue, &scene.textures);
+
+        let bvh_scene = flatten(BVHTree::new(
+            bvh_builder,
+            &scene.spheres,
+            &scene.vertices,
+            &scene.triangles,
+        ));
+        // an empty scene gets a dummy node that skips straight to the end
+        let bvh_buffer = storage_buffer(device, "BVH Buffer", &bvh_scene);
+
+        // count followed by the indecies of every light sphere,
+        // padded so the runtime sized array is never empty
+        let mut light_spheres: Vec<u32> = vec![0];
+        light_spheres.extend(
+            (0..scene.spheres.len() as u32)
+                .filter(|&i| scene.spheres[i as usize].mat_type == indecies::LIGHT),
+        );
+        light_spheres[0] = light_spheres.len() as u32 - 1;
+        if light_spheres.len() == 1 {
+            light_spheres.push(0);
+        }
+        let light_spheres_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Light Spheres Buffer"),
+            contents: &light_spheres.bytes(),
             usage: wgpu::BufferUsages::STORAGE,
         });
 
-        let bvh_scene = flatten(BVHTree::new(scene.spheres));
-        let bvh_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("BVH Buffer"),
-            contents: &bvh_scene.bytes(),
-            usage: wgpu::BufferUsages::STORAGE,
-        });
+        // get the sky onto the gpu
+        let environment = EnvironmentBuffers::new(device, queue, &scene.environment);
 
         Self {
             spheres_buffer,
+            vertices_buffer,
+            triangles_buffer,
             lights_buffer,
             lambertians_buffer,
             metals_buffer,
             glass_buffer,
+            conductors_buffer,
+            rough_glass_buffer,
+            principled_buffer,
+            textures,
             bvh_buffer,
+            light_spheres_buffer,
+            environment,
         }
     }
 }
 
+/// Storage buffers can't be bound with a size of zero,
+/// so an empty slice is uploaded as a single default element.
+fn storage_buffer<T: Bytes + Default>(
+    device: &wgpu::Device,
+    label: &str,
+    contents: &[T],
+) -> wgpu::Buffer {
+    let contents = if contents.is_empty() {
+        T::default().bytes()
+    } else {
+        contents.bytes()
+    };
+    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some(label),
+        contents: &contents,
+        usage: wgpu::BufferUsages::STORAGE,
+    })
+}
+
 pub struct Renderer {
     compute_pipeline: ComputePipeline,
 
     scene_buffers: SceneBuffers,
     camera_buffer: wgpu::Buffer,
+    /// camera before the last update, reprojected from
+    previous_camera_buffer: wgpu::Buffer,
     camera_config: CameraConfig,
     scene_bind_group: wgpu::BindGroup,
     globals: Globals,
+    bvh_builder: BVHBuilder,
+    tone_mapping: ToneMapping,
+    outlier_rejection: f32,
 
     texture: Texture,
-
+    /// sum of every frame's squared colour, for estimating how noisy the texture still is
+    moments: Texture,
+    aovs: Aovs,
+    /// aovs asked for by the settings, temporal reprojection and the denoiser need them too
+    aovs_enabled: bool,
+    history: History,
+    temporal: bool,
+    denoising: bool,
+
+    /// every frame's seed is derived from this and the frame's sample count once it's set,
+    /// otherwise from this and `frames`
+    seed: u64,
+    fixed_seed: bool,
+    /// frames rendered so far, unlike samples it never starts over
+    frames: u64,
     samples: i32,
     dirty: bool,
+    /// the camera moved and the history gets reprojected
+    moved: bool,
 }
 impl Renderer {
     pub fn new(
         device: &wgpu::Device,
+        queue: &wgpu::Queue,
         scene: Scene,
         settings: Settings,
         width: u32,
@@ -114,7 +235,7 @@ impl Renderer {
 
         let camera_config = CameraConfig::new(scene.camera, width as f32 / height as f32);
 
-        let scene_buffers = SceneBuffers::new(device, scene);
+        let scene_buffers = SceneBuffers::new(device, queue, scene, settings.bvh);
 
         // get camera onto the gpu
         let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
@@ -122,13 +243,27 @@ impl Renderer {
             contents: &camera_config.build().bytes(),
             usage: wgpu::BufferUsages::UNIFORM,
         });
+        let previous_camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Camera Config Buffer"),
+            contents: &camera_config.build().bytes(),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
 
         let scene_bind_group = make_scene_bind_group(device, &compute_pipeline, &scene_buffers);
 
-        let globals = Globals::new(rand::random(), settings.samples, settings.depth);
+        let mut globals = Globals::new(0, settings.samples, settings.depth, [width, height]);
+        globals.adaptive_threshold = settings.adaptive_threshold;
+        globals.indirect_clamp = settings.indirect_clamp;
+        globals.total_clamp = settings.total_clamp;
+        globals.aovs = (settings.aovs || settings.temporal) as u32;
 
         let texture = Texture::new(device, width, height);
+        let moments = Texture::new(device, width, height);
+        // sized by the first render
+        let aovs = Aovs::new(device, 1, 1);
+        let history = History::new(device);
 
+        let seed = rand::random();
         let samples = -1;
         let dirty = true;
 
@@ -136,18 +271,32 @@ impl Renderer {
             compute_pipeline,
             scene_buffers,
             camera_buffer,
+            previous_camera_buffer,
             camera_config,
             scene_bind_group,
             globals,
+            bvh_builder: settings.bvh,
+            tone_mapping: settings.tone_mapping,
+            outlier_rejection: settings.outlier_rejection,
             texture,
+            moments,
+            aovs,
+            aovs_enabled: settings.aovs,
+            history,
+            temporal: settings.temporal,
+            denoising: false,
+            seed,
+            fixed_seed: false,
+            frames: 0,
             samples,
             dirty,
+            moved: false,
         }
     }
 
-    pub fn reload_scene(&mut self, device: &wgpu::Device, scene: Scene) {
+    pub fn reload_scene(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, scene: Scene) {
         self.camera_config = CameraConfig::new(scene.camera, self.camera_config.aspect);
-        self.scene_buffers = SceneBuffers::new(device, scene);
+        self.scene_buffers = SceneBuffers::new(device, queue, scene, self.bvh_builder);
 
         self.scene_bind_group =
             make_scene_bind_group(device, &self.compute_pipeline, &self.scene_buffers);
@@ -157,43 +306,95 @@ impl Renderer {
     pub fn reload_settings(&mut self, settings: &Settings) {
         self.globals.samples = settings.samples;
         self.globals.depth = settings.depth;
+        self.globals.adaptive_threshold = settings.adaptive_threshold;
+        self.globals.indirect_clamp = settings.indirect_clamp;
+        self.globals.total_clamp = settings.total_clamp;
+        self.aovs_enabled = settings.aovs;
+        self.set_temporal(settings.temporal);
+        self.bvh_builder = settings.bvh;
+        self.tone_mapping = settings.tone_mapping;
+        self.outlier_rejection = settings.outlier_rejection;
     }
 
     pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
         self.texture.resize(device, width, height);
+        self.moments.resize(device, width, height);
         self.camera_config.aspect = width as f32 / height as f32;
+        self.globals.tile_offset = [0, 0];
+        self.globals.frame_size = [width, height];
+        self.dirty = true;
+    }
+
+    /// Renders the tile of a bigger frame at offset into a texture of the tile's size,
+    /// the tile is reset by [`Renderer::resize`].
+    pub fn set_tile(
+        &mut self,
+        device: &wgpu::Device,
+        offset: [u32; 2],
+        size: [u32; 2],
+        frame_size: [u32; 2],
+    ) {
+        let tex_size = self.texture.desc().size;
+        if [tex_size.width, tex_size.height] != size {
+            self.texture.resize(device, size[0], size[1]);
+            self.moments.resize(device, size[0], size[1]);
+        }
+        self.camera_config.aspect = frame_size[0] as f32 / frame_size[1] as f32;
+        self.globals.tile_offset = offset;
+        self.globals.frame_size = frame_size;
         self.dirty = true;
     }
 
     pub fn render(&mut self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder) {
         if self.dirty {
-            self.camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("Camera Config Buffer"),
-                contents: &self.camera_config.build().bytes(),
-                usage: wgpu::BufferUsages::UNIFORM,
-            });
+            self.update_camera(device);
+            self.update_aovs(device);
+            if self.globals.aovs != 0 {
+                self.aovs.clear(encoder);
+            }
 
             let _render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render Pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: self.texture.view(),
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.0,
-                            g: 0.0,
-                            b: 0.0,
-                            a: 1.0,
-                        }),
-                        store: true,
-                    },
-                })],
+                color_attachments: &[
+                    Some(wgpu::RenderPassColorAttachment {
+                        view: self.texture.view(),
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            // alpha counts every pixel's frames
+                            load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                            store: true,
+                        },
+                    }),
+                    Some(wgpu::RenderPassColorAttachment {
+                        view: self.moments.view(),
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                            store: true,
+                        },
+                    }),
+                ],
                 depth_stencil_attachment: None,
             });
             self.dirty = false;
+            self.moved = false;
             self.samples = 0;
+        } else if self.moved {
+            self.update_camera(device);
+            self.history
+                .copy(device, encoder, &self.texture, &self.moments, &self.aovs);
+            // the aovs start over with the new view
+            self.aovs.clear(encoder);
         }
 
+        // a camera move starts the samples over, which would freeze the noise on screen
+        let frame = if self.fixed_seed {
+            self.samples as u64
+        } else {
+            self.frames
+        };
+        self.globals.seed = frame_seed(self.seed, frame);
+        self.frames += 1;
         let seed_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Global Buffer"),
             contents: &self.globals.bytes(),
@@ -227,32 +428,202 @@ impl Renderer {
                         size: None,
                     }),
                 },
+                // second moments
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(self.moments.view()),
+                },
+                // aovs
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(self.aovs.albedo.view()),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(self.aovs.normal.view()),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: wgpu::BindingResource::TextureView(self.aovs.position.view()),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: wgpu::BindingResource::TextureView(self.aovs.id.view()),
+                },
+                // history
+                wgpu::BindGroupEntry {
+                    binding: 8,
+                    resource: wgpu::BindingResource::TextureView(self.history.texture.view()),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 9,
+                    resource: wgpu::BindingResource::TextureView(self.history.moments.view()),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 10,
+                    resource: wgpu::BindingResource::TextureView(self.history.albedo.view()),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 11,
+                    resource: wgpu::BindingResource::TextureView(self.history.position.view()),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 12,
+                    resource: wgpu::BindingResource::TextureView(self.history.id.view()),
+                },
+                // previous camera
+                wgpu::BindGroupEntry {
+                    binding: 13,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &self.previous_camera_buffer,
+                        offset: 0,
+                        size: None,
+                    }),
+                },
             ],
         });
 
         let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor::default());
-        cpass.set_pipeline(self.compute_pipeline.pipeline());
         cpass.set_bind_group(0, &main_bind_group, &[]);
         cpass.set_bind_group(1, &self.scene_bind_group, &[]);
         let t_desc = self.texture.desc();
         let width = (t_desc.size.width as f32 / 16.0).ceil() as u32;
         let height = (t_desc.size.height as f32 / 16.0).ceil() as u32;
+        if self.moved {
+            cpass.set_pipeline(self.compute_pipeline.reproject_pipeline());
+            cpass.dispatch_workgroups(width, height, 1);
+            self.moved = false;
+        }
+        cpass.set_pipeline(self.compute_pipeline.pipeline());
         cpass.dispatch_workgroups(width, height, 1);
 
-        self.globals.seed = rand::random();
         self.samples += 1;
     }
 
+    fn update_camera(&mut self, device: &wgpu::Device) {
+        let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Camera Config Buffer"),
+            contents: &self.camera_config.build().bytes(),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        self.previous_camera_buffer = std::mem::replace(&mut self.camera_buffer, camera_buffer);
+    }
+
+    /// Sizes the aov textures like the output, or back to 1x1 when they're turned off.
+    fn update_aovs(&mut self, device: &wgpu::Device) {
+        let size = self.texture.desc().size;
+        if self.globals.aovs != 0 {
+            self.aovs.resize(device, size.width, size.height);
+        } else {
+            self.aovs.resize(device, 1, 1);
+        }
+    }
+
+    /// Continues accumulating on top of the raw sums of an earlier render,
+    /// every image has to be the size of the texture. Aovs without sums start over.
+    pub fn resume(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        accumulation: &image::Rgba32FImage,
+        moments: &image::Rgba32FImage,
+        aov_sums: Option<&[image::Rgba32FImage; 4]>,
+        samples: i32,
+    ) {
+        if self.dirty {
+            self.update_camera(device);
+            self.update_aovs(device);
+            self.dirty = false;
+        }
+
+        write_texture(queue, &self.texture, accumulation);
+        write_texture(queue, &self.moments, moments);
+        if self.globals.aovs != 0 {
+            match aov_sums {
+                Some(sums) => {
+                    for (texture, sum) in self.aovs.textures().into_iter().zip(sums) {
+                        write_texture(queue, texture, sum);
+                    }
+                }
+                None => {
+                    let size = self.texture.desc().size;
+                    let zero = image::Rgba32FImage::new(size.width, size.height);
+                    for texture in self.aovs.textures() {
+                        write_texture(queue, texture, &zero);
+                    }
+                }
+            }
+        }
+        self.samples = samples;
+    }
+
+    /// Makes the following frames reproducible, every frame's seed only depends on
+    /// this and the samples so far.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.fixed_seed = true;
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
     pub fn samples(&self) -> i32 {
         self.samples
     }
 
+    pub fn tone_mapping(&self) -> ToneMapping {
+        self.tone_mapping
+    }
+
+    pub fn outlier_rejection(&self) -> f32 {
+        self.outlier_rejection
+    }
+
     pub fn texture(&self) -> &Texture {
         &self.texture
     }
 
+    pub fn moments(&self) -> &Texture {
+        &self.moments
+    }
+
+    pub fn aovs(&self) -> &Aovs {
+        &self.aovs
+    }
+
+    /// Keeps the aovs on for a denoiser while it's used, whatever the settings say.
+    /// The accumulation starts over when the aovs get turned on or off.
+    pub fn set_denoising(&mut self, denoising: bool) {
+        self.denoising = denoising;
+        self.update_aov_flag();
+    }
+
+    /// Turns temporal reprojection on or off, see [`Settings::temporal`].
+    pub fn set_temporal(&mut self, temporal: bool) {
+        self.temporal = temporal;
+        self.update_aov_flag();
+    }
+
+    pub fn temporal(&self) -> bool {
+        self.temporal
+    }
+
+    fn update_aov_flag(&mut self) {
+        let aovs = (self.aovs_enabled || self.temporal || self.denoising) as u32;
+        if self.globals.aovs != aovs {
+            self.globals.aovs = aovs;
+            self.dirty = true;
+        }
+    }
+
+    /// Starts the accumulation over, unless temporal reprojection is on.
     pub fn camera_config_mut(&mut self) -> &mut CameraConfig {
-        self.dirty = true;
+        if self.temporal {
+            self.moved = true;
+        } else {
+            self.dirty = true;
+        }
         &mut self.camera_config
     }
 
@@ -265,53 +636,33 @@ impl Renderer {
         device: &wgpu::Device,
         encoder: &mut wgpu::CommandEncoder,
     ) -> SaveInfo {
-        use std::num::NonZeroU32;
-        let tex_desc = self.texture.desc();
-        let tex_width = tex_desc.size.width;
-        let tex_height = tex_desc.size.height;
-        // wgpu requires texture -> buffer copies to be aligned using
-        // wgpu::COPY_BYTES_PER_ROW_ALIGNMENT. Because of this we'll
-        // need to save both the padded_bytes_per_row as well as the
-        // unpadded_bytes_per_row
-        let pixel_size = std::mem::size_of::<[f32; 4]>() as u32;
-        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
-        let unpadded_bytes_per_row = pixel_size * tex_width;
-        let padding = (align - unpadded_bytes_per_row % align) % align;
-        let padded_bytes_per_row = unpadded_bytes_per_row + padding;
-
-        let output_buffer_desc = wgpu::BufferDescriptor {
-            size: (padded_bytes_per_row * tex_height) as u64,
-            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
-            label: None,
-            mapped_at_creation: false,
-        };
-        let buffer = device.create_buffer(&output_buffer_desc);
-        encoder.copy_texture_to_buffer(
-            wgpu::ImageCopyTexture {
-                texture: self.texture.texture(),
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-                aspect: wgpu::TextureAspect::All,
-            },
-            wgpu::ImageCopyBuffer {
-                buffer: &buffer,
-                layout: wgpu::ImageDataLayout {
-                    offset: 0,
-                    bytes_per_row: Some(NonZeroU32::new(padded_bytes_per_row).unwrap()),
-                    rows_per_image: None,
-                    // rows_per_image: Some(NonZeroU32::new(tex_height).unwrap()),
-                },
-            },
-            tex_desc.size,
-        );
+        copy_texture(device, encoder, &self.texture)
+    }
+
+    /// Like [`Renderer::start_save`], but for the sums of squared colours.
+    pub fn start_save_moments(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+    ) -> SaveInfo {
+        copy_texture(device, encoder, &self.moments)
+    }
 
-        SaveInfo {
-            buffer,
-            padded: padded_bytes_per_row,
-            unpadded: unpadded_bytes_per_row,
-            tex_width,
-            tex_height,
+    /// Like [`Renderer::start_save`], but for albedo, normal, position and id.
+    /// None when aovs are turned off.
+    pub fn start_save_aovs(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+    ) -> Option<[SaveInfo; 4]> {
+        if self.globals.aovs == 0 {
+            return None;
         }
+        Some(
+            self.aovs
+                .textures()
+                .map(|texture| copy_texture(device, encoder, texture)),
+        )
     }
 
     pub fn globals(&self) -> Globals {
@@ -319,6 +670,88 @@ impl Renderer {
     }
 }
 
+fn copy_texture(
+    device: &wgpu::Device,
+    encoder: &mut wgpu::CommandEncoder,
+    texture: &Texture,
+) -> SaveInfo {
+    use std::num::NonZeroU32;
+    let tex_desc = texture.desc();
+    let tex_width = tex_desc.size.width;
+    let tex_height = tex_desc.size.height;
+    // wgpu requires texture -> buffer copies to be aligned using
+    // wgpu::COPY_BYTES_PER_ROW_ALIGNMENT. Because of this we'll
+    // need to save both the padded_bytes_per_row as well as the
+    // unpadded_bytes_per_row
+    let pixel_size = std::mem::size_of::<[f32; 4]>() as u32;
+    let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    let unpadded_bytes_per_row = pixel_size * tex_width;
+    let padding = (align - unpadded_bytes_per_row % align) % align;
+    let padded_bytes_per_row = unpadded_bytes_per_row + padding;
+
+    let output_buffer_desc = wgpu::BufferDescriptor {
+        size: (padded_bytes_per_row * tex_height) as u64,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        label: None,
+        mapped_at_creation: false,
+    };
+    let buffer = device.create_buffer(&output_buffer_desc);
+    encoder.copy_texture_to_buffer(
+        wgpu::ImageCopyTexture {
+            texture: texture.texture(),
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+            aspect: wgpu::TextureAspect::All,
+        },
+        wgpu::ImageCopyBuffer {
+            buffer: &buffer,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(NonZeroU32::new(padded_bytes_per_row).unwrap()),
+                rows_per_image: None,
+                // rows_per_image: Some(NonZeroU32::new(tex_height).unwrap()),
+            },
+        },
+        tex_desc.size,
+    );
+
+    SaveInfo {
+        buffer,
+        padded: padded_bytes_per_row,
+        unpadded: unpadded_bytes_per_row,
+        tex_width,
+        tex_height,
+    }
+}
+
+fn write_texture(queue: &wgpu::Queue, texture: &Texture, image: &image::Rgba32FImage) {
+    use std::num::NonZeroU32;
+    let size = texture.desc().size;
+    queue.write_texture(
+        wgpu::ImageCopyTexture {
+            texture: texture.texture(),
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+            aspect: wgpu::TextureAspect::All,
+        },
+        bytemuck::cast_slice(image.as_raw()),
+        wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: NonZeroU32::new(size.width * std::mem::size_of::<[f32; 4]>() as u32),
+            rows_per_image: None,
+        },
+        size,
+    );
+}
+
+/// splitmix64 of the seed and frame
+fn frame_seed(seed: u64, frame: u64) -> u32 {
+    let mut z = seed.wrapping_add(frame.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    (z ^ (z >> 31)) as u32
+}
+
 fn make_scene_bind_group(
     device: &wgpu::Device,
     pipeline: &ComputePipeline,
@@ -381,6 +814,96 @@ fn make_scene_bind_group(
                     size: None,
                 }),
             },
+            // vertices
+            wgpu::BindGroupEntry {
+                binding: 6,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &scene.vertices_buffer,
+                    offset: 0,
+                    size: None,
+                }),
+            },
+            // triangles
+            wgpu::BindGroupEntry {
+                binding: 7,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &scene.triangles_buffer,
+                    offset: 0,
+                    size: None,
+                }),
+            },
+            // light spheres
+            wgpu::BindGroupEntry {
+                binding: 8,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &scene.light_spheres_buffer,
+                    offset: 0,
+                    size: None,
+                }),
+            },
+            // environment
+            wgpu::BindGroupEntry {
+                binding: 9,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &scene.environment.uniform_buffer,
+                    offset: 0,
+                    size: None,
+                }),
+            },
+            // environment texture
+            wgpu::BindGroupEntry {
+                binding: 10,
+                resource: wgpu::BindingResource::TextureView(&scene.environment.view),
+            },
+            // environment distribution
+            wgpu::BindGroupEntry {
+                binding: 11,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &scene.environment.distribution_buffer,
+                    offset: 0,
+                    size: None,
+                }),
+            },
+            // conductors
+            wgpu::BindGroupEntry {
+                binding: 12,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &scene.conductors_buffer,
+                    offset: 0,
+                    size: None,
+                }),
+            },
+            // rough glass
+            wgpu::BindGroupEntry {
+                binding: 13,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &scene.rough_glass_buffer,
+                    offset: 0,
+                    size: None,
+                }),
+            },
+            // principled
+            wgpu::BindGroupEntry {
+                binding: 14,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &scene.principled_buffer,
+                    offset: 0,
+                    size: None,
+                }),
+            },
+            // textures
+            wgpu::BindGroupEntry {
+                binding: 15,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &scene.textures.info_buffer,
+                    offset: 0,
+                    size: None,
+                }),
+            },
+            wgpu::BindGroupEntry {
+                binding: 16,
+                resource: wgpu::BindingResource::TextureView(&scene.textures.view),
+            },
         ],
     })
 }
@@ -393,7 +916,30 @@ pub struct SaveInfo {
     tex_height: u32,
 }
 impl SaveInfo {
-    pub fn finish(self, device: &wgpu::Device, samples: i32) {
+    /// Waits for the copy to finish and averages the accumulated samples,
+    /// the result is still in linear light.
+    /// Pixels `outlier_rejection` standard deviations brighter than their neighbours are
+    /// replaced, see [`outliers::reject_outliers`].
+    pub fn finish(self, device: &wgpu::Device, outlier_rejection: f32) -> image::Rgba32FImage {
+        let mut img = self.read(device);
+
+        // alpha is how many frames the pixel got
+        for p in img.pixels_mut() {
+            let frames = 1.0 / p[3].max(1.0);
+            p[0] *= frames;
+            p[1] *= frames;
+            p[2] *= frames;
+            p[3] = 1.0;
+        }
+        if outlier_rejection > 0.0 {
+            outliers::reject_outliers(&mut img, outlier_rejection);
+        }
+
+        img
+    }
+
+    /// Waits for the copy to finish and returns the raw sums of the accumulated samples.
+    pub fn read(self, device: &wgpu::Device) -> image::Rgba32FImage {
         let buffer_slice = self.buffer.slice(..);
 
         let (tx, rx) = std::sync::mpsc::channel();
@@ -410,28 +956,12 @@ impl SaveInfo {
             .copied()
             .collect::<Vec<_>>();
 
-        let mut img = image::Rgba32FImage::from_raw(
+        image::Rgba32FImage::from_raw(
             self.tex_width,
             self.tex_height,
             bytemuck::cast_slice(&data).to_vec(),
         )
-        .unwrap();
-
-        let gamma = 1.0 / 2.2;
-        let samples = 1.0 / samples as f32;
-        for p in img.pixels_mut() {
-            p[0] *= samples;
-            p[1] *= samples;
-            p[2] *= samples;
-
-            p[0] = p[0].powf(gamma);
-            p[1] = p[1].powf(gamma);
-            p[2] = p[2].powf(gamma);
-        }
-        let img = image::DynamicImage::ImageRgba32F(img);
-        let img = img.to_rgb8();
-
-        img.save("img.png").unwrap();
+        .unwrap()
     }
 
     pub fn tex_width(&self) -> u32 {
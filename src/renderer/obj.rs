@@ -0,0 +1,148 @@
+use super::{
+    materials::*,
+    scene::Scene,
+    triangle::{Triangle, Vertex},
+    vector3::Vector3,
+};
+
+/// obj file placed into a scene
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct ObjInstance {
+    pub path: String,
+    #[serde(default)]
+    pub pos: Vector3,
+    #[serde(default = "default_scale")]
+    pub scale: f32,
+}
+fn default_scale() -> f32 {
+    1.0
+}
+
+/// Loads the geometry of an obj file and the materials of its mtl file into the scene.
+/// Meshes without a material get a default lambertian.
+pub fn load(obj: &ObjInstance, scene: &mut Scene) -> Result<(), tobj::LoadError> {
+    let (models, materials) = tobj::load_obj(&obj.path, &tobj::GPU_LOAD_OPTIONS)?;
+    let materials = match materials {
+        Ok(materials) => materials,
+        Err(e) => {
+            log::warn!("{}: couldn't load materials: {e}", obj.path);
+            vec![]
+        }
+    };
+    // mat_type and mat_index of every mtl material
+    let materials: Vec<(u32, u32)> = materials
+        .iter()
+        .map(|material| add_material(material, scene))
+        .collect();
+    let mut default_material = None;
+
+    for model in models {
+        let mesh = model.mesh;
+
+        let (mat_type, mat_index) = match mesh.material_id.and_then(|id| materials.get(id)) {
+            Some(&material) => material,
+            None => *default_material.get_or_insert_with(|| {
+                scene.lambertians.push(Lambertian::default());
+                (indecies::LAMBERTIAN, scene.lambertians.len() as u32 - 1)
+            }),
+        };
+
+        let offset = scene.vertices.len() as u32;
+        let has_normals = mesh.normals.len() == mesh.positions.len();
+        for (i, p) in mesh.positions.chunks_exact(3).enumerate() {
+            let pos = Vector3::new(p[0], p[1], p[2]) * obj.scale + obj.pos;
+
+            let mut norm = Vector3::ZERO;
+            if has_normals {
+                let n = &mesh.normals[i * 3..i * 3 + 3];
+                let n = Vector3::new(n[0], n[1], n[2]);
+                if n.length_sqrd() > 0.0 {
+                    // a negative scale mirrors the mesh inside out
+                    norm = n.normal() * obj.scale.signum();
+                }
+            }
+
+            scene.vertices.push(Vertex::new(pos, norm));
+        }
+
+        for t in mesh.indices.chunks_exact(3) {
+            scene.triangles.push(Triangle::new(
+                [t[0] + offset, t[1] + offset, t[2] + offset],
+                mat_type,
+                mat_index,
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Maps an mtl material onto the closest material the renderer has,
+/// materials using the pbr extension become principled.
+/// Returns its mat_type and mat_index.
+fn add_material(material: &tobj::Material, scene: &mut Scene) -> (u32, u32) {
+    let colour = |c: [f32; 3]| Vector3::new(c[0], c[1], c[2]);
+    let brightest = |c: Vector3| c.x.max(c.y).max(c.z);
+
+    let diffuse = material
+        .diffuse
+        .map(colour)
+        .unwrap_or(Lambertian::default().albedo);
+    let specular = material.specular.map(colour).unwrap_or(Vector3::ZERO);
+    let emissive = material.emissive.map(colour).unwrap_or(Vector3::ZERO);
+    let dissolve = material.dissolve.unwrap_or(1.0);
+    // illumination models that refract
+    let transparent = matches!(material.illumination_model, Some(4 | 6 | 7 | 9));
+
+    // the pbr extension of mtl, tobj leaves its keys unparsed
+    let pbr = |key: &str| {
+        material
+            .unknown_param
+            .get(key)
+            .and_then(|value| value.split_whitespace().next()?.parse::<f32>().ok())
+    };
+    if ["Pr", "Pm", "Ps", "Pc", "Pcr"]
+        .iter()
+        .any(|&key| pbr(key).is_some())
+    {
+        let default = Principled::default();
+        scene.principled.push(Principled {
+            base_colour: diffuse,
+            metallic: pbr("Pm").unwrap_or(0.0),
+            roughness: pbr("Pr").unwrap_or(default.roughness),
+            transmission: if transparent { 1.0 } else { 1.0 - dissolve },
+            ir: material.optical_density.unwrap_or(default.ir),
+            clearcoat: pbr("Pc").unwrap_or(0.0),
+            clearcoat_roughness: pbr("Pcr").unwrap_or(default.clearcoat_roughness),
+            sheen: pbr("Ps").unwrap_or(0.0),
+            surface: Surface {
+                emission: Emission {
+                    colour: emissive,
+                    strength: 1.0,
+                    texture: None,
+                },
+                ..Surface::NONE
+            },
+            ..default
+        });
+        return (indecies::PRINCIPLED, scene.principled.len() as u32 - 1);
+    }
+
+    if brightest(emissive) > 0.0 {
+        scene.lights.push(Light::new(emissive));
+        (indecies::LIGHT, scene.lights.len() as u32 - 1)
+    } else if dissolve < 1.0 || transparent {
+        let ir = material.optical_density.unwrap_or(Glass::default().ir);
+        scene.glass.push(Glass::new(ir));
+        (indecies::GLASS, scene.glass.len() as u32 - 1)
+    } else if brightest(specular) > brightest(diffuse) {
+        // shininess is a phong exponent, usually in [0, 1000]
+        let shininess = material.shininess.unwrap_or(0.0).max(0.0);
+        let roughness = (2.0 / (shininess + 2.0)).sqrt();
+        scene.metals.push(Metal::new(specular, roughness));
+        (indecies::METAL, scene.metals.len() as u32 - 1)
+    } else {
+        scene.lambertians.push(Lambertian::new(diffuse));
+        (indecies::LAMBERTIAN, scene.lambertians.len() as u32 - 1)
+    }
+}
@@ -0,0 +1,96 @@
+/// flat neighbourhoods have no deviation, so it's at least this much of their mean
+/// to keep pixels that are just a bit brighter
+const MIN_DEVIATION: f32 = 0.1;
+
+/// Replaces pixels whose luminance is more than `threshold` standard deviations above
+/// the mean of their neighbours with the neighbours' mean colour.
+/// Expects averaged colours, like [`super::SaveInfo::finish`] makes.
+pub fn reject_outliers(img: &mut image::Rgba32FImage, threshold: f32) {
+    let luminance = |p: &image::Rgba<f32>| 0.2126 * p[0] + 0.7152 * p[1] + 0.0722 * p[2];
+
+    // neighbours are read from the original so replacements don't spread
+    let original = img.clone();
+    let (width, height) = original.dimensions();
+    for (x, y, p) in img.enumerate_pixels_mut() {
+        let mut colour = [0.0; 3];
+        let mut l = 0.0;
+        let mut l2 = 0.0;
+        let mut count = 0.0;
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                let (nx, ny) = (x as i64 + dx, y as i64 + dy);
+                if (dx, dy) == (0, 0)
+                    || nx < 0
+                    || ny < 0
+                    || nx >= width as i64
+                    || ny >= height as i64
+                {
+                    continue;
+                }
+                let n = original.get_pixel(nx as u32, ny as u32);
+                for c in 0..3 {
+                    colour[c] += n[c];
+                }
+                let lum = luminance(n);
+                l += lum;
+                l2 += lum * lum;
+                count += 1.0;
+            }
+        }
+        if count == 0.0 {
+            continue;
+        }
+
+        let mean = l / count;
+        let deviation = (l2 / count - mean * mean)
+            .max(0.0)
+            .sqrt()
+            .max(MIN_DEVIATION * mean);
+        if luminance(p) > mean + threshold * deviation {
+            for c in 0..3 {
+                p[c] = colour[c] / count;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fireflies_are_replaced() {
+        let mut img = image::Rgba32FImage::from_pixel(3, 3, image::Rgba([0.5, 0.5, 0.5, 1.0]));
+        img.put_pixel(1, 1, image::Rgba([100.0, 50.0, 80.0, 1.0]));
+        // a bright edge isn't an outlier
+        img.put_pixel(2, 0, image::Rgba([0.8, 0.8, 0.8, 1.0]));
+        img.put_pixel(2, 1, image::Rgba([0.8, 0.8, 0.8, 1.0]));
+        img.put_pixel(2, 2, image::Rgba([0.8, 0.8, 0.8, 1.0]));
+
+        let mut rejected = img.clone();
+        reject_outliers(&mut rejected, 3.0);
+        // five neighbours at 0.5 and three at 0.8
+        let p = rejected.get_pixel(1, 1);
+        assert!((p[0] - 0.6125).abs() < 1e-6 && p[0] == p[1] && p[1] == p[2]);
+        for (x, y, p) in rejected.enumerate_pixels() {
+            if (x, y) != (1, 1) {
+                assert_eq!(p, img.get_pixel(x, y));
+            }
+        }
+    }
+
+    #[test]
+    fn slightly_brighter_pixels_in_flat_areas_are_kept() {
+        let mut img = image::Rgba32FImage::from_pixel(3, 3, image::Rgba([0.5, 0.5, 0.5, 1.0]));
+        img.put_pixel(1, 1, image::Rgba([0.6, 0.6, 0.6, 1.0]));
+
+        let mut rejected = img.clone();
+        reject_outliers(&mut rejected, 3.0);
+        assert_eq!(rejected, img);
+
+        // a firefly is still far above the floor
+        img.put_pixel(1, 1, image::Rgba([50.0, 50.0, 50.0, 1.0]));
+        reject_outliers(&mut img, 3.0);
+        assert_eq!(img.get_pixel(1, 1), &image::Rgba([0.5, 0.5, 0.5, 1.0]));
+    }
+}
@@ -1,16 +1,37 @@
-use crate::renderer::{materials::*, sphere::Sphere};
+use crate::renderer::{
+    materials::*,
+    obj::{self, ObjInstance},
+    sphere::Sphere,
+    triangle::{Triangle, Vertex},
+};
 
-use super::{camera::CameraSettings, vector3::Vector3};
+use super::{
+    camera::CameraSettings,
+    environment::EnvironmentSettings,
+    textures::MaterialTexture,
+    vector3::{self, Vector3},
+};
 
+/// Obj files referenced by a scene file are loaded into the scene when it's deserialized,
+/// so serializing a scene writes their geometry and materials out inline.
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(try_from = "SceneFile")]
 pub struct Scene {
     pub camera: CameraSettings,
     pub spheres: Vec<Sphere>,
+    pub vertices: Vec<Vertex>,
+    pub triangles: Vec<Triangle>,
+    pub environment: EnvironmentSettings,
 
     pub lights: Vec<Light>,
     pub lambertians: Vec<Lambertian>,
     pub metals: Vec<Metal>,
     pub glass: Vec<Glass>,
+    pub conductors: Vec<Conductor>,
+    pub rough_glass: Vec<RoughGlass>,
+    pub principled: Vec<Principled>,
+    /// referenced by the materials' texture index
+    pub textures: Vec<MaterialTexture>,
 }
 impl Default for Scene {
     fn default() -> Self {
@@ -22,10 +43,200 @@ impl Default for Scene {
                 Sphere::new(Vector3::Y, 1.0, indecies::GLASS, 0),
                 Sphere::new(-Vector3::Y, 1.0, indecies::METAL, 0),
             ],
+            vertices: vec![],
+            triangles: vec![],
+            environment: Default::default(),
             lights: vec![Light::default()],
             lambertians: vec![Lambertian::default()],
             metals: vec![Metal::default()],
             glass: vec![Glass::default()],
+            conductors: vec![],
+            rough_glass: vec![],
+            principled: vec![],
+            textures: vec![],
         }
     }
 }
+
+impl Scene {
+    /// Surface area of the spheres and triangles using each light, lights in watts spread over it.
+    pub fn light_areas(&self) -> Vec<f32> {
+        let mut areas = vec![0.0; self.lights.len()];
+        for sphere in &self.spheres {
+            if sphere.mat_type == indecies::LIGHT {
+                areas[sphere.mat_index as usize] +=
+                    4.0 * std::f32::consts::PI * sphere.rad * sphere.rad;
+            }
+        }
+        for triangle in &self.triangles {
+            if triangle.mat_type == indecies::LIGHT {
+                let [a, b, c] = triangle.indecies.map(|i| self.vertices[i as usize].pos);
+                areas[triangle.mat_index as usize] +=
+                    vector3::cross(&(b - a), &(c - a)).length() / 2.0;
+            }
+        }
+        areas
+    }
+    /// every sphere and triangle using a light has to point at one of the lights
+    fn check_lights(&self) -> Result<(), String> {
+        let lights = self.lights.len();
+        let spheres = self
+            .spheres
+            .iter()
+            .map(|s| ("sphere", s.mat_type, s.mat_index));
+        let triangles = self
+            .triangles
+            .iter()
+            .map(|t| ("triangle", t.mat_type, t.mat_index));
+        for (i, (shape, mat_type, mat_index)) in spheres.enumerate().chain(triangles.enumerate()) {
+            if mat_type == indecies::LIGHT && mat_index as usize >= lights {
+                return Err(format!(
+                    "{shape} {i} uses light {mat_index}, there are only {lights}"
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// every texture index of the materials has to be one of the textures
+    fn check_textures(&self) -> Result<(), String> {
+        let textures = self.textures.len();
+        let check = |material: &str, i: usize, texture: Option<u32>| match texture {
+            Some(t) if t as usize >= textures => Err(format!(
+                "{material} {i} uses texture {t}, there are only {textures}"
+            )),
+            _ => Ok(()),
+        };
+        let check_surface = |material: &str, i: usize, surface: &Surface| {
+            check(material, i, surface.normal_map)?;
+            check(material, i, surface.bump_map)?;
+            check(material, i, surface.emission.texture)
+        };
+
+        for (i, light) in self.lights.iter().enumerate() {
+            check("light", i, light.texture)?;
+        }
+        for (i, m) in self.lambertians.iter().enumerate() {
+            check("lambertian", i, m.texture)?;
+            check_surface("lambertian", i, &m.surface)?;
+        }
+        for (i, m) in self.metals.iter().enumerate() {
+            check("metal", i, m.texture)?;
+            check_surface("metal", i, &m.surface)?;
+        }
+        for (i, m) in self.glass.iter().enumerate() {
+            check_surface("glass", i, &m.surface)?;
+        }
+        for (i, m) in self.conductors.iter().enumerate() {
+            check_surface("conductor", i, &m.surface)?;
+        }
+        for (i, m) in self.rough_glass.iter().enumerate() {
+            check_surface("rough glass", i, &m.surface)?;
+        }
+        for (i, m) in self.principled.iter().enumerate() {
+            check("principled", i, m.texture)?;
+            check_surface("principled", i, &m.surface)?;
+        }
+        Ok(())
+    }
+
+    /// every light in nits, ready for the gpu
+    pub fn lights_in_nits(&self) -> Vec<Light> {
+        self.lights
+            .iter()
+            .zip(self.light_areas())
+            .map(|(light, area)| light.in_nits(area))
+            .collect()
+    }
+}
+
+/// Scene as it's written in a ron file
+#[derive(serde::Deserialize)]
+struct SceneFile {
+    camera: CameraSettings,
+    spheres: Vec<Sphere>,
+    #[serde(default)]
+    vertices: Vec<Vertex>,
+    #[serde(default)]
+    triangles: Vec<Triangle>,
+    #[serde(default)]
+    objs: Vec<ObjInstance>,
+    #[serde(default)]
+    environment: EnvironmentSettings,
+
+    lights: Vec<Light>,
+    lambertians: Vec<Lambertian>,
+    metals: Vec<Metal>,
+    glass: Vec<Glass>,
+    #[serde(default)]
+    conductors: Vec<Conductor>,
+    #[serde(default)]
+    rough_glass: Vec<RoughGlass>,
+    #[serde(default)]
+    principled: Vec<Principled>,
+    #[serde(default)]
+    textures: Vec<MaterialTexture>,
+}
+impl TryFrom<SceneFile> for Scene {
+    type Error = String;
+
+    fn try_from(file: SceneFile) -> Result<Self, Self::Error> {
+        let mut scene = Self {
+            camera: file.camera,
+            spheres: file.spheres,
+            vertices: file.vertices,
+            triangles: file.triangles,
+            environment: file.environment,
+            lights: file.lights,
+            lambertians: file.lambertians,
+            metals: file.metals,
+            glass: file.glass,
+            conductors: file.conductors,
+            rough_glass: file.rough_glass,
+            principled: file.principled,
+            textures: file.textures,
+        };
+
+        for instance in &file.objs {
+            obj::load(instance, &mut scene).map_err(|e| format!("{}: {e}", instance.path))?;
+        }
+        scene.check_lights()?;
+        scene.check_textures()?;
+
+        Ok(scene)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn texture_indices_are_checked() {
+        let mut scene = Scene::default();
+        assert!(scene.check_textures().is_ok());
+
+        scene.lambertians[0].surface.bump_map = Some(0);
+        assert_eq!(
+            scene.check_textures(),
+            Err("lambertian 0 uses texture 0, there are only 0".to_string())
+        );
+        scene.textures.push(MaterialTexture::Gradient {
+            from: Vector3::ZERO,
+            to: Vector3::ONE,
+        });
+        assert!(scene.check_textures().is_ok());
+    }
+
+    #[test]
+    fn light_indices_are_checked() {
+        let mut scene = Scene::default();
+        assert!(scene.check_lights().is_ok());
+
+        scene.spheres[1].mat_index = 1;
+        assert_eq!(
+            scene.check_lights(),
+            Err("sphere 1 uses light 1, there are only 1".to_string())
+        );
+    }
+}
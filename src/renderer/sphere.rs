@@ -2,7 +2,14 @@ use super::{bytes::Bytes, vector3::Vector3};
 
 #[repr(C)]
 #[derive(
-    Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable, serde::Serialize, serde::Deserialize,
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    bytemuck::Pod,
+    bytemuck::Zeroable,
+    serde::Serialize,
+    serde::Deserialize,
 )]
 pub struct Sphere {
     pub pos: Vector3,
@@ -0,0 +1,260 @@
+use wgpu::util::DeviceExt;
+
+use super::{bytes::Bytes, environment::load_image, tonemap::srgb_eotf, vector3::Vector3};
+
+pub mod indecies {
+    pub const IMAGE: u32 = 0;
+    pub const CHECKER: u32 = 1;
+    pub const GRADIENT: u32 = 2;
+    pub const NOISE: u32 = 3;
+}
+
+/// texture index of materials without a texture
+pub const NO_TEXTURE: u32 = u32::MAX;
+
+/// Colour looked up at a hit's uv, materials multiply their colour by it.
+/// Whole number scales keep the seam of a sphere invisible.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub enum MaterialTexture {
+    /// path to an image, repeated outside of [0, 1]. Without `srgb` .hdr and .exr files are
+    /// linear and everything else is srgb encoded, normal and bump maps need `srgb: Some(false)`
+    Image {
+        path: String,
+        #[serde(default)]
+        srgb: Option<bool>,
+    },
+    /// squares alternating between two colours, scale of them along u and v
+    Checker {
+        even: Vector3,
+        odd: Vector3,
+        scale: f32,
+    },
+    /// from one colour at v = 0 to the other at v = 1
+    Gradient { from: Vector3, to: Vector3 },
+    /// fractal perlin noise between two colours, every octave doubles the detail
+    Noise {
+        low: Vector3,
+        high: Vector3,
+        scale: f32,
+        octaves: u32,
+    },
+}
+
+/// float formats hold linear light, the 8 bit ones are usually srgb encoded
+fn srgb_default(path: &str) -> bool {
+    !matches!(
+        image::ImageFormat::from_path(path),
+        Ok(image::ImageFormat::Hdr | image::ImageFormat::OpenExr)
+    )
+}
+
+/// What the shader needs to know about a texture, images are a layer of the image array.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct TextureInfo {
+    colour_a: Vector3,
+    texture_type: u32,
+    colour_b: Vector3,
+    scale: f32,
+    layer: u32,
+    octaves: u32,
+}
+impl Default for TextureInfo {
+    fn default() -> Self {
+        Self {
+            colour_a: Vector3::ONE,
+            texture_type: indecies::GRADIENT,
+            colour_b: Vector3::ONE,
+            scale: 1.0,
+            layer: 0,
+            octaves: 1,
+        }
+    }
+}
+impl Bytes for TextureInfo {
+    fn bytes(&self) -> Vec<u8> {
+        let byte = [0u8; 4];
+        let mut v = vec![];
+
+        v.extend(bytemuck::bytes_of(self));
+        v.extend(byte);
+        v.extend(byte);
+
+        v
+    }
+}
+
+/// Material textures on the gpu, the image array has a 1x1 white layer when there are no images.
+pub struct TextureBuffers {
+    pub info_buffer: wgpu::Buffer,
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+}
+impl TextureBuffers {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, textures: &[MaterialTexture]) -> Self {
+        let mut images = vec![];
+        let infos: Vec<TextureInfo> = textures
+            .iter()
+            .map(|texture| match texture {
+                MaterialTexture::Image { path, srgb } => {
+                    let mut image = load_image(path).unwrap_or_else(|e| {
+                        log::error!("{path}: couldn't load texture: {e}");
+                        image::Rgba32FImage::from_pixel(1, 1, image::Rgba([1.0; 4]))
+                    });
+                    // the shader works in linear light
+                    if srgb.unwrap_or_else(|| srgb_default(path)) {
+                        decode_srgb(&mut image);
+                    }
+                    images.push(image);
+                    TextureInfo {
+                        texture_type: indecies::IMAGE,
+                        layer: images.len() as u32 - 1,
+                        ..Default::default()
+                    }
+                }
+                &MaterialTexture::Checker { even, odd, scale } => TextureInfo {
+                    colour_a: even,
+                    texture_type: indecies::CHECKER,
+                    colour_b: odd,
+                    scale,
+                    ..Default::default()
+                },
+                &MaterialTexture::Gradient { from, to } => TextureInfo {
+                    colour_a: from,
+                    colour_b: to,
+                    ..Default::default()
+                },
+                &MaterialTexture::Noise {
+                    low,
+                    high,
+                    scale,
+                    octaves,
+                } => TextureInfo {
+                    colour_a: low,
+                    texture_type: indecies::NOISE,
+                    colour_b: high,
+                    scale,
+                    octaves: octaves.max(1),
+                    ..Default::default()
+                },
+            })
+            .collect();
+
+        let mut info_bytes = infos.bytes();
+        // storage buffers can't be empty
+        if info_bytes.is_empty() {
+            info_bytes = TextureInfo::default().bytes();
+        }
+        let info_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Texture Info Buffer"),
+            contents: &info_bytes,
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+
+        let (width, height, layers) = layers(&images);
+        let texture = device.create_texture_with_data(
+            queue,
+            &wgpu::TextureDescriptor {
+                label: Some("Material Textures"),
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: images.len().max(1) as u32,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba32Float,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING,
+            },
+            bytemuck::cast_slice(&layers),
+        );
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+
+        Self {
+            info_buffer,
+            texture,
+            view,
+        }
+    }
+}
+
+fn decode_srgb(image: &mut image::Rgba32FImage) {
+    for p in image.pixels_mut() {
+        for c in 0..3 {
+            p[c] = srgb_eotf(p[c]);
+        }
+    }
+}
+
+/// Every image resized to the largest width and height so they fit into one texture array,
+/// returns the size and the pixels of every layer one after another.
+fn layers(images: &[image::Rgba32FImage]) -> (u32, u32, Vec<f32>) {
+    if images.is_empty() {
+        return (1, 1, vec![1.0; 4]);
+    }
+    let width = images.iter().map(|image| image.width()).max().unwrap();
+    let height = images.iter().map(|image| image.height()).max().unwrap();
+
+    let mut layers = Vec::with_capacity((width * height * 4) as usize * images.len());
+    for image in images {
+        if image.dimensions() == (width, height) {
+            layers.extend_from_slice(image.as_raw());
+        } else {
+            let resized = image::imageops::resize(
+                image,
+                width,
+                height,
+                image::imageops::FilterType::Triangle,
+            );
+            layers.extend_from_slice(resized.as_raw());
+        }
+    }
+    (width, height, layers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layers_share_the_largest_size() {
+        let small = image::Rgba32FImage::from_pixel(1, 2, image::Rgba([0.5; 4]));
+        let large = image::Rgba32FImage::from_pixel(4, 1, image::Rgba([1.0; 4]));
+        let (width, height, pixels) = layers(&[small, large]);
+        assert_eq!((width, height), (4, 2));
+        assert_eq!(pixels.len(), 2 * 4 * 2 * 4);
+        let (first, second) = pixels.split_at(4 * 2 * 4);
+        assert!(first.iter().all(|&c| (c - 0.5).abs() < 1e-6));
+        assert!(second.iter().all(|&c| (c - 1.0).abs() < 1e-6));
+
+        assert_eq!(layers(&[]), (1, 1, vec![1.0; 4]));
+    }
+
+    #[test]
+    fn srgb_images_are_decoded_to_linear() {
+        let mut image = image::Rgba32FImage::from_pixel(1, 1, image::Rgba([0.0, 0.5, 1.0, 0.5]));
+        decode_srgb(&mut image);
+        let p = image.get_pixel(0, 0);
+        assert_eq!(p[0], 0.0);
+        assert!((p[1] - 0.214_041).abs() < 1e-5);
+        assert!((p[2] - 1.0).abs() < 1e-6);
+        // alpha isn't a colour
+        assert_eq!(p[3], 0.5);
+
+        let texture: MaterialTexture = ron::from_str("Image(path: \"wood.png\")").unwrap();
+        assert!(matches!(texture, MaterialTexture::Image { srgb: None, .. }));
+        assert!(srgb_default("wood.png"));
+        assert!(srgb_default("wood.JPG"));
+        assert!(!srgb_default("sky.hdr"));
+        assert!(!srgb_default("sky.exr"));
+    }
+
+    #[test]
+    fn info_matches_the_shader_layout() {
+        assert_eq!(TextureInfo::default().bytes().len(), 48);
+    }
+}
@@ -0,0 +1,184 @@
+use super::bytes::Bytes;
+
+// src/render.wgsl has a copy of these operators, keep them the same
+pub mod indecies {
+    pub const CLAMP: u32 = 0;
+    pub const REINHARD: u32 = 1;
+    pub const ACES: u32 = 2;
+    pub const AGX: u32 = 3;
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum ToneMapper {
+    /// values above 1 clip
+    #[default]
+    Clamp,
+    Reinhard,
+    /// Narkowicz's fit of the ACES filmic curve
+    Aces,
+    /// minimal AgX approximation
+    Agx,
+}
+
+/// How the accumulated light is turned into colours on screen and in 8 bit images.
+#[derive(Clone, Copy, Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct ToneMapping {
+    /// exposure in stops
+    #[serde(default)]
+    pub exposure: f32,
+    #[serde(default)]
+    pub operator: ToneMapper,
+}
+impl ToneMapping {
+    /// Maps linear light to display linear light in [0, 1], before the srgb oetf.
+    pub fn map(&self, rgb: [f32; 3]) -> [f32; 3] {
+        let scale = self.exposure.exp2();
+        let rgb = rgb.map(|c| (c * scale).max(0.0));
+
+        let rgb = match self.operator {
+            ToneMapper::Clamp => rgb,
+            ToneMapper::Reinhard => rgb.map(|c| c / (1.0 + c)),
+            ToneMapper::Aces => {
+                rgb.map(|c| (c * (2.51 * c + 0.03)) / (c * (2.43 * c + 0.59) + 0.14))
+            }
+            ToneMapper::Agx => agx(rgb),
+        };
+
+        rgb.map(|c| c.clamp(0.0, 1.0))
+    }
+
+    /// Uniform for the display shader.
+    pub fn display(&self, samples: i32, encode_srgb: bool, heatmap: bool) -> Display {
+        Display {
+            samples,
+            exposure: self.exposure.exp2(),
+            tone_mapper: match self.operator {
+                ToneMapper::Clamp => indecies::CLAMP,
+                ToneMapper::Reinhard => indecies::REINHARD,
+                ToneMapper::Aces => indecies::ACES,
+                ToneMapper::Agx => indecies::AGX,
+            },
+            encode_srgb: encode_srgb as u32,
+            heatmap: heatmap as u32,
+        }
+    }
+}
+
+/// column major
+type Matrix3 = [[f32; 3]; 3];
+
+const AGX_INSET: Matrix3 = [
+    [0.842_479_06, 0.042_328_24, 0.042_375_655],
+    [0.078_433_6, 0.878_468_6, 0.078_433_6],
+    [0.079_223_745, 0.079_166_13, 0.879_143],
+];
+const AGX_OUTSET: Matrix3 = [
+    [1.196_879, -0.052_896_85, -0.052_971_635],
+    [-0.098_020_88, 1.151_903_1, -0.098_043_45],
+    [-0.099_029_74, -0.098_961_18, 1.151_073_7],
+];
+const AGX_MIN_EV: f32 = -12.473_93;
+const AGX_MAX_EV: f32 = 4.026_069;
+
+fn agx(rgb: [f32; 3]) -> [f32; 3] {
+    let mul = |m: &Matrix3, v: [f32; 3]| {
+        [0, 1, 2].map(|r| m[0][r] * v[0] + m[1][r] * v[1] + m[2][r] * v[2])
+    };
+
+    // log encoding of the inset colour
+    let rgb = mul(&AGX_INSET, rgb).map(|c| {
+        let ev = c.max(1e-10).log2().clamp(AGX_MIN_EV, AGX_MAX_EV);
+        (ev - AGX_MIN_EV) / (AGX_MAX_EV - AGX_MIN_EV)
+    });
+    // sigmoid contrast curve
+    let rgb = rgb.map(|x| {
+        let x2 = x * x;
+        let x4 = x2 * x2;
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x
+            - 0.00232
+    });
+
+    mul(&AGX_OUTSET, rgb).map(|c| c.max(0.0).powf(2.2))
+}
+
+/// srgb transfer function, display linear to encoded
+pub fn srgb_oetf(c: f32) -> f32 {
+    if c <= 0.003_130_8 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// inverse of [`srgb_oetf`], encoded to linear
+pub fn srgb_eotf(c: f32) -> f32 {
+    if c <= 0.040_45 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Display {
+    /// samples a pixel gets without adaptive sampling
+    samples: i32,
+    /// linear scale
+    exposure: f32,
+    tone_mapper: u32,
+    /// surfaces without an srgb format need the oetf applied in the shader
+    encode_srgb: u32,
+    /// shows how many samples every pixel got instead of the image
+    heatmap: u32,
+}
+impl Bytes for Display {
+    fn bytes(&self) -> Vec<u8> {
+        Vec::from(bytemuck::bytes_of(self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn operators_stay_in_range() {
+        for operator in [
+            ToneMapper::Clamp,
+            ToneMapper::Reinhard,
+            ToneMapper::Aces,
+            ToneMapper::Agx,
+        ] {
+            let tone_mapping = ToneMapping {
+                exposure: 0.0,
+                operator,
+            };
+            let mut last = tone_mapping.map([0.0; 3])[1];
+            assert!(last < 0.01, "{operator:?} maps black to {last}");
+            for i in 1..100 {
+                let c = tone_mapping.map([i as f32 * 0.5; 3])[1];
+                assert!((0.0..=1.0).contains(&c));
+                assert!(c >= last - 1e-4, "{operator:?} isn't monotonic");
+                last = c;
+            }
+        }
+    }
+
+    #[test]
+    fn exposure_is_in_stops() {
+        let tone_mapping = ToneMapping {
+            exposure: 1.0,
+            operator: ToneMapper::Clamp,
+        };
+        assert_eq!(tone_mapping.map([0.25, 0.5, 2.0]), [0.5, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn srgb_oetf_is_continuous() {
+        let knee = 0.003_130_8;
+        assert!((srgb_oetf(knee) - srgb_oetf(knee + 1e-7)).abs() < 1e-4);
+        assert_eq!(srgb_oetf(0.0), 0.0);
+        assert!((srgb_oetf(1.0) - 1.0).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,77 @@
+use super::{bytes::Bytes, vector3::Vector3};
+
+#[repr(C)]
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    bytemuck::Pod,
+    bytemuck::Zeroable,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+pub struct Vertex {
+    pub pos: Vector3,
+    /// shading normal, a zero normal means the triangle is flat shaded
+    #[serde(default)]
+    pub norm: Vector3,
+}
+
+impl Vertex {
+    pub const fn new(pos: Vector3, norm: Vector3) -> Self {
+        Self { pos, norm }
+    }
+}
+
+impl Bytes for Vertex {
+    fn bytes(&self) -> Vec<u8> {
+        let b_pos = bytemuck::bytes_of(&self.pos);
+        let b_norm = bytemuck::bytes_of(&self.norm);
+        let byte = [0u8; 4];
+        let mut v = vec![];
+
+        v.extend(b_pos);
+        v.extend(byte);
+
+        v.extend(b_norm);
+        v.extend(byte);
+
+        v
+    }
+}
+
+#[repr(C)]
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    bytemuck::Pod,
+    bytemuck::Zeroable,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+pub struct Triangle {
+    /// indecies into the vertex buffer
+    pub indecies: [u32; 3],
+
+    pub mat_type: u32,
+    pub mat_index: u32,
+}
+
+impl Triangle {
+    pub const fn new(indecies: [u32; 3], mat_type: u32, mat_index: u32) -> Self {
+        Self {
+            indecies,
+            mat_type,
+            mat_index,
+        }
+    }
+}
+
+impl Bytes for Triangle {
+    fn bytes(&self) -> Vec<u8> {
+        Vec::from(bytemuck::bytes_of(self))
+    }
+}
@@ -5,6 +5,7 @@
     Clone,
     Copy,
     Debug,
+    Default,
     PartialEq,
     bytemuck::Pod,
     bytemuck::Zeroable,
@@ -50,6 +51,18 @@ pub fn min(lhs: &Vector3, rhs: &Vector3) -> Vector3 {
 }
 
 use std::ops::*;
+impl Index<usize> for Vector3 {
+    type Output = f32;
+
+    fn index(&self, index: usize) -> &Self::Output {
+        match index {
+            0 => &self.x,
+            1 => &self.y,
+            2 => &self.z,
+            _ => panic!("Vector3 index out of range: {index}"),
+        }
+    }
+}
 impl Add for Vector3 {
     type Output = Self;
 
@@ -154,6 +167,12 @@ mod tests {
         assert_eq!(v2 / 2.0, Vector3::new(5.0, 5.5, 6.0));
     }
 
+    #[test]
+    fn index() {
+        let v = Vector3::new(1.0, 2.0, 3.0);
+        assert_eq!([v[0], v[1], v[2]], [1.0, 2.0, 3.0]);
+    }
+
     #[test]
     fn normal() {
         let mut v = Vector3::new(20.0, 0.0, 0.0);
@@ -35,7 +35,8 @@ impl WgpuContext {
             .request_device(
                 &wgpu::DeviceDescriptor {
                     features: wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES,
-                    limits: wgpu::Limits::default(),
+                    // the scene needs more storage buffers than the default limit allows
+                    limits: adapter.limits(),
                     label: None,
                 },
                 None,